@@ -0,0 +1,41 @@
+#!/usr/bin/env bash
+# 按照 Cargo.toml 中列出的 feature 组合逐个编译, clippy 和运行测试, 任意一个失败就退出
+# 用法: scripts/check-features.sh
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+# 和 Cargo.toml 中 "支持的 feature 组合" 保持一致
+combos=(
+    ""
+    "strict-ata"
+    "indexer-logs"
+    "strict-ata,indexer-logs"
+    "std"
+    "std,borsh"
+    "std,borsh,strict-ata,indexer-logs"
+)
+
+# 链上程序必须保持 no_std 并且不分配堆内存 (见 src/lib.rs 开头)
+# 默认 feature 下 crate 是 no_std, 只要没有 extern crate alloc, Vec / String / format! 就无法编译,
+# 所以下面默认组合的编译通过就说明核心代码没有用到堆内存
+if grep -rn "extern crate alloc" src/; then
+    echo "error: the on-chain core must not link alloc" >&2
+    exit 1
+fi
+
+# 安装了 Solana 工具链时, 再用默认 feature 编译一次链上程序, 确认可以链接
+if command -v cargo-build-sbf >/dev/null 2>&1; then
+    echo "==> cargo build-sbf"
+    cargo build-sbf
+fi
+
+for features in "${combos[@]}"; do
+    echo "==> features: [${features}]"
+    cargo build --lib --no-default-features --features "${features}"
+    cargo clippy --all-targets --no-default-features --features "${features}" -- -D warnings
+    # 有些测试只在对应的 feature 下编译 (比如 indexer-logs 的事件日志)
+    cargo test --no-default-features --features "${features}"
+done
+
+echo "all feature combinations build and pass tests"
@@ -14,6 +14,34 @@ pub enum EscrowError {
     InvalidAccountData,
     // 地址无效
     InvalidAddress,
+    // vault 账户被冻结 (mint 设置了默认冻结状态)
+    FrozenVault,
+    // 接收 token 的账户被冻结
+    FrozenDestination,
+    // 传入的 mint 和 escrow 中记录的不一致
+    MintMismatch,
+    // escrow 要求的数量超过了 taker 能接受的上限
+    SlippageExceeded,
+    // escrow PDA 地址无法派生或和传入的账户不一致
+    InvalidEscrowPda,
+    // 不支持的指令数据版本
+    UnsupportedVersion,
+    // 转账的 authority 不是源账户的 owner 或 delegate
+    InvalidAuthority,
+    // token program 既不是 spl token program 也不是 token 2022 program
+    InvalidTokenProgram,
+    // seed 在保留范围内
+    ReservedSeed,
+    // 已经过了截止时间
+    Expired,
+    // 部分成交的数量超过了 escrow 剩余的 receive
+    PartialFillTooLarge,
+    // mint_a 和 mint_b 是同一个 mint
+    DuplicateMint,
+    // escrow 有元数据账户, 关闭 escrow 时没有传入
+    MetadataRequired,
+    // UpdateExpiry 设置的过期时间超出了允许的范围
+    UpdateOutOfBounds,
 }
 
 // 为 ProgramError 实现 From trait
@@ -35,6 +63,100 @@ impl fmt::Display for EscrowError {
             EscrowError::InvalidOwner => write!(f, "非法的所有者"),
             EscrowError::InvalidAccountData => write!(f, "非法的账户数据"),
             EscrowError::InvalidAddress => write!(f, "非法的地址"),
+            EscrowError::FrozenVault => write!(f, "vault 账户被冻结"),
+            EscrowError::FrozenDestination => write!(f, "接收账户被冻结"),
+            EscrowError::MintMismatch => write!(f, "mint 和 escrow 记录的不一致"),
+            EscrowError::SlippageExceeded => write!(f, "超过了可接受的最大数量"),
+            EscrowError::InvalidEscrowPda => write!(f, "非法的 escrow PDA"),
+            EscrowError::UnsupportedVersion => write!(f, "不支持的版本"),
+            EscrowError::InvalidAuthority => write!(f, "非法的转账授权账户"),
+            EscrowError::InvalidTokenProgram => write!(f, "非法的 token program"),
+            EscrowError::ReservedSeed => write!(f, "seed 在保留范围内"),
+            EscrowError::Expired => write!(f, "已经过了截止时间"),
+            EscrowError::PartialFillTooLarge => write!(f, "部分成交的数量超过了剩余数量"),
+            EscrowError::DuplicateMint => write!(f, "mint_a 和 mint_b 不能相同"),
+            EscrowError::MetadataRequired => write!(f, "必须传入 escrow 的元数据账户"),
+            EscrowError::UpdateOutOfBounds => write!(f, "新的过期时间超出了允许的范围"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    // 固定大小的栈上缓冲区, 不使用 String, 和链上程序一样不分配堆内存
+    struct Buffer {
+        bytes: [u8; 128],
+        len: usize,
+    }
+
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.bytes.len() {
+                return Err(fmt::Error);
+            }
+            self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    fn render(err: EscrowError) -> Buffer {
+        let mut buffer = Buffer {
+            bytes: [0; 128],
+            len: 0,
+        };
+        write!(buffer, "{err}").unwrap();
+        buffer
+    }
+
+    #[test]
+    fn display_every_variant() {
+        let cases = [
+            (
+                EscrowError::NotEnoughRentExempt,
+                "Lamports balance below rent-exempt threshold",
+            ),
+            (EscrowError::NotSigner, "没有签名"),
+            (EscrowError::InvalidOwner, "非法的所有者"),
+            (EscrowError::InvalidAccountData, "非法的账户数据"),
+            (EscrowError::InvalidAddress, "非法的地址"),
+            (EscrowError::FrozenVault, "vault 账户被冻结"),
+            (EscrowError::FrozenDestination, "接收账户被冻结"),
+            (EscrowError::MintMismatch, "mint 和 escrow 记录的不一致"),
+            (EscrowError::SlippageExceeded, "超过了可接受的最大数量"),
+            (EscrowError::InvalidEscrowPda, "非法的 escrow PDA"),
+            (EscrowError::UnsupportedVersion, "不支持的版本"),
+            (EscrowError::InvalidAuthority, "非法的转账授权账户"),
+            (EscrowError::InvalidTokenProgram, "非法的 token program"),
+            (EscrowError::ReservedSeed, "seed 在保留范围内"),
+            (EscrowError::Expired, "已经过了截止时间"),
+            (
+                EscrowError::PartialFillTooLarge,
+                "部分成交的数量超过了剩余数量",
+            ),
+            (EscrowError::DuplicateMint, "mint_a 和 mint_b 不能相同"),
+            (
+                EscrowError::MetadataRequired,
+                "必须传入 escrow 的元数据账户",
+            ),
+            (
+                EscrowError::UpdateOutOfBounds,
+                "新的过期时间超出了允许的范围",
+            ),
+        ];
+
+        // 新增变体时必须同时加到上面的列表中
+        assert_eq!(cases.len(), EscrowError::UpdateOutOfBounds as usize + 1);
+
+        for (i, (err, expected)) in cases.into_iter().enumerate() {
+            // 列表按照错误编号排列
+            assert_eq!(err as usize, i);
+            let buffer = render(err);
+            assert_eq!(&buffer.bytes[..buffer.len], expected.as_bytes());
         }
     }
 }
@@ -2,17 +2,27 @@ use crate::errors::EscrowError;
 use pinocchio::{
     cpi::{Seed, Signer},
     error::ProgramError,
-    sysvars::{rent::Rent, Sysvar},
+    sysvars::{clock::Clock, rent::Rent, Sysvar},
     AccountView, Address, ProgramResult,
 };
 use pinocchio_associated_token_account::instructions::Create;
 use pinocchio_system::instructions::CreateAccount;
+use pinocchio_token::instructions::{CloseAccount, Transfer};
 use solana_address::address;
+use solana_program_log::{log, Logger};
 
+// 账户校验 trait, 统一命名为 AccountCheck (没有 AccountChecker)
+// 每种账户类型 (SignerAccount, MintInterface, ProgramAccount 等) 各自实现, 指令中通过 Xxx::check(account) 调用
 pub trait AccountCheck {
     fn check(account: &AccountView) -> Result<(), ProgramError>;
 }
 
+// 指令数据解析 trait
+// 每个指令的数据结构体都实现它, 没有数据的指令也一样 (只校验数据为空)
+pub trait InstructionData: Sized {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError>;
+}
+
 // ATA 账户校验 trait
 pub trait AssociatedTokenAccountCheck {
     // 验证账户是否是指定 owner、mint 和 token_program 的 ATA
@@ -63,6 +73,109 @@ impl AccountCheck for SignerAccount {
     }
 }
 
+// 账户数量不对时, 先打印日志再返回 NotEnoughAccountKeys
+// 标准错误不会说明是哪个指令以及期望的数量, 日志方便客户端排查账户顺序的问题
+// 日志格式: "escrow:<ix> expected N accounts, got M"
+#[cold]
+pub fn not_enough_account_keys(ix: &str, expected: usize, got: usize) -> ProgramError {
+    let mut logger = Logger::<64>::default();
+    logger
+        .append("escrow:")
+        .append(ix)
+        .append(" expected ")
+        .append(expected)
+        .append(" accounts, got ")
+        .append(got);
+    logger.log();
+
+    ProgramError::NotEnoughAccountKeys
+}
+
+// 输出给索引器的事件日志, 只在 indexer-logs feature 下编译
+// 格式: ESCROW_EVENT {"kind":"take","seed":1,"amount":100}
+// amount 是这次指令中转移的 token a 数量
+// 使用栈上的固定大小缓冲区, 整数由 Logger 格式化, 不需要堆内存
+#[cfg(feature = "indexer-logs")]
+pub fn log_event(kind: &str, seed: u64, amount: u64) {
+    event_line(kind, seed, amount).log();
+}
+
+// 拼接事件日志, 和输出分开, 测试中可以直接检查内容
+#[cfg(feature = "indexer-logs")]
+fn event_line(kind: &str, seed: u64, amount: u64) -> Logger<96> {
+    let mut logger = Logger::<96>::default();
+    logger
+        .append("ESCROW_EVENT {\"kind\":\"")
+        .append(kind)
+        .append("\",\"seed\":")
+        .append(seed)
+        .append(",\"amount\":")
+        .append(amount)
+        .append("}");
+    logger
+}
+
+// 读取当前的 unix timestamp
+pub fn unix_timestamp() -> Result<i64, ProgramError> {
+    Ok(Clock::get()?.unix_timestamp)
+}
+
+// 判断截止时间 (unix timestamp) 是否已经过了, Make 使用它
+// deadline 为 0 表示不限制, 不读取 Clock
+pub fn deadline_passed(deadline: i64) -> Result<bool, ProgramError> {
+    if deadline == 0 {
+        return Ok(false);
+    }
+
+    Ok(deadline_passed_at(deadline, unix_timestamp()?))
+}
+
+// 用给定的当前时间判断截止时间是否已经过了, 所有时间检查的规则都在这里
+// - deadline 为 0 表示不限制
+// - now 不是正数时 (本地或测试 validator 的时钟没有设置), 记录警告并当作没有过期
+//   避免把读取异常的时钟当作 "所有 escrow 都已经过期"
+// - 等于 deadline 时还没有过期
+pub fn deadline_passed_at(deadline: i64, now: i64) -> bool {
+    if deadline == 0 {
+        return false;
+    }
+
+    if now <= 0 {
+        log("escrow: clock unix_timestamp is not set, deadline not enforced");
+        return false;
+    }
+
+    now > deadline
+}
+
+// 校验账户互不相同
+// 同一个账户可以在指令的账户列表中出现多次, 比如 maker 和 taker 是同一个账户
+// 或者 vault 同时作为转账的目标账户, 会导致转账和关闭的结果出错
+pub fn check_distinct(accounts: &[&AccountView]) -> ProgramResult {
+    for (i, a) in accounts.iter().enumerate() {
+        if accounts[i + 1..].iter().any(|b| a.address() == b.address()) {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+    }
+
+    Ok(())
+}
+
+// 可写账户校验
+// 会被修改的账户 (lamports 或数据) 必须是可写的
+// 提前校验, 避免在 CPI 深处才失败, 得到一个含糊的错误
+pub struct WritableAccount;
+
+impl AccountCheck for WritableAccount {
+    fn check(account: &AccountView) -> Result<(), ProgramError> {
+        if !account.is_writable() {
+            return Err(ProgramError::Immutable);
+        }
+
+        Ok(())
+    }
+}
+
 // system 账户校验
 pub struct SystemAccount;
 
@@ -78,8 +191,63 @@ impl AccountCheck for SystemAccount {
     }
 }
 
+// 未初始化账户校验
+// 创建账户之前确认账户是空的: 没有数据, 并且 owner 是 system program (或者还不存在)
+// 没有数据就不可能带有旧的数据, CreateAccount 创建的账户数据由运行时清零, 所以创建之后不需要再检查内容
+pub struct UninitializedAccount;
+
+impl AccountCheck for UninitializedAccount {
+    fn check(account: &AccountView) -> Result<(), ProgramError> {
+        if !account.is_data_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        // 不存在的账户 lamports 为 0, owner 也是 system program
+        if !account.owned_by(&pinocchio_system::ID) {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        Ok(())
+    }
+}
+
 // Token 账户相关
 pub const TOKEN_2022_PROGRAM_ID: Address = address!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+// spl token program 的 wrapped SOL mint
+pub const NATIVE_MINT: Address = address!("So11111111111111111111111111111111111111112");
+// 所有支持的 token program
+pub const TOKEN_PROGRAM_IDS: [Address; 2] = [pinocchio_token::ID, TOKEN_2022_PROGRAM_ID];
+
+// 校验传入的 token program 账户是 spl token program 或 token 2022 program
+// 必须在用 token_program 派生 ATA 地址或者 CPI 之前调用
+pub fn validate_token_program(account: &AccountView) -> ProgramResult {
+    if !TOKEN_PROGRAM_IDS.contains(account.address()) {
+        return Err(EscrowError::InvalidTokenProgram.into());
+    }
+
+    Ok(())
+}
+
+// 校验传入的 associated token program 账户
+// 创建 ATA 的 CPI 依赖它, 传错的话在 CPI 中才会失败, 错误信息不明确
+pub fn validate_associated_token_program(account: &AccountView) -> ProgramResult {
+    if account
+        .address()
+        .ne(&pinocchio_associated_token_account::ID)
+    {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    Ok(())
+}
+
+// 检查账户是否被其中任意一个程序拥有
+#[inline(always)]
+pub fn owned_by_any(account: &AccountView, programs: &[Address]) -> bool {
+    programs.iter().any(|program| account.owned_by(program))
+}
+
 // token 2022 账户数据的总 bytes 是 165 个字节, 之后的是 extension 数据
 // 165 = [0 ~ 164]
 // 第 165 个字节是 AccountType 判别字节偏移量, 0 = Uninitialized, 1 = Mint, 2 = Account
@@ -90,6 +258,225 @@ pub const TOKEN_2022_MINT_DISCRIMINATOR: u8 = 0x01;
 // token 2022 token account 账户的判别字节
 // AccountType = 2
 pub const TOKEN_2022_TOKEN_ACCOUNT_DISCRIMINATOR: u8 = 0x02;
+// AccountType 判别字节之后就是 extension 的 TLV 数据
+// 每个 extension: [type: u16][length: u16][value: length bytes]
+pub const TOKEN_2022_EXTENSIONS_OFFSET: usize = TOKEN_2022_ACCOUNT_DISCRIMINATOR_OFFSET + 1;
+// DefaultAccountState extension 的类型值, value 是 1 个字节的 AccountState
+pub const TOKEN_2022_DEFAULT_ACCOUNT_STATE_EXTENSION: u16 = 6;
+// AccountState::Frozen
+pub const TOKEN_ACCOUNT_STATE_FROZEN: u8 = 2;
+// token account 中 amount 字段的偏移量
+// pinocchio-token/src/state/token.rs 中 amount 在结构体的第 64 位开始
+pub const TOKEN_ACCOUNT_AMOUNT_OFFSET: usize = 64;
+// token account 中 owner 字段的偏移量
+pub const TOKEN_ACCOUNT_OWNER_OFFSET: usize = 32;
+// token account 中 delegate 字段的偏移量, COption<Address>: [tag: u32][address: 32 bytes]
+pub const TOKEN_ACCOUNT_DELEGATE_OFFSET: usize = 72;
+// token account 中 state 字段的偏移量
+// mint(32) + owner(32) + amount(8) + delegate(4 + 32) = 108
+pub const TOKEN_ACCOUNT_STATE_OFFSET: usize = 108;
+
+// 在 token 2022 账户数据中查找指定类型的 extension, 返回它的 value
+pub fn find_token_2022_extension(data: &[u8], extension_type: u16) -> Option<&[u8]> {
+    let mut offset = TOKEN_2022_EXTENSIONS_OFFSET;
+
+    while offset + 4 <= data.len() {
+        let ty = u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let len = u16::from_le_bytes([data[offset + 2], data[offset + 3]]) as usize;
+
+        // 类型为 0 (Uninitialized) 说明后面没有 extension 了
+        if ty == 0 {
+            return None;
+        }
+
+        let start = offset + 4;
+        let end = start.checked_add(len)?;
+        if end > data.len() {
+            return None;
+        }
+
+        if ty == extension_type {
+            return Some(&data[start..end]);
+        }
+
+        offset = end;
+    }
+
+    None
+}
+
+// 校验账户的 lamports 足够支付当前数据长度的租金
+// 用作创建账户之后的后置检查, 防止 CPI 创建出来的账户不是 rent exempt
+pub fn check_rent_exempt(account: &AccountView) -> ProgramResult {
+    check_min_lamports(
+        account,
+        Rent::get()?.try_minimum_balance(account.data_len())?,
+    )
+}
+
+// 校验账户至少有 minimum 个 lamports, 不足时返回 NotEnoughRentExempt
+// 租金的最小值由调用方通过 Rent::get() 计算
+#[inline(always)]
+pub fn check_min_lamports(account: &AccountView, minimum: u64) -> ProgramResult {
+    if account.lamports() < minimum {
+        return Err(EscrowError::NotEnoughRentExempt.into());
+    }
+
+    Ok(())
+}
+
+// 读取 token account 的余额
+pub fn read_token_amount(account: &AccountView) -> Result<u64, ProgramError> {
+    let data = account.try_borrow()?;
+
+    if data.len() < TOKEN_ACCOUNT_AMOUNT_OFFSET + size_of::<u64>() {
+        return Err(EscrowError::InvalidAccountData.into());
+    }
+
+    Ok(u64::from_le_bytes(
+        data[TOKEN_ACCOUNT_AMOUNT_OFFSET..TOKEN_ACCOUNT_AMOUNT_OFFSET + size_of::<u64>()]
+            .try_into()
+            .unwrap(),
+    ))
+}
+
+// 读取 token account 的 owner
+pub fn read_token_owner(account: &AccountView) -> Result<Address, ProgramError> {
+    let data = account.try_borrow()?;
+
+    if data.len() < TOKEN_ACCOUNT_OWNER_OFFSET + size_of::<Address>() {
+        return Err(EscrowError::InvalidAccountData.into());
+    }
+
+    Ok(Address::new_from_array(
+        data[TOKEN_ACCOUNT_OWNER_OFFSET..TOKEN_ACCOUNT_OWNER_OFFSET + size_of::<Address>()]
+            .try_into()
+            .unwrap(),
+    ))
+}
+
+// 读取 token account 的 mint, mint 是 token account 的第一个字段
+pub fn read_token_mint(account: &AccountView) -> Result<Address, ProgramError> {
+    let data = account.try_borrow()?;
+
+    if data.len() < size_of::<Address>() {
+        return Err(EscrowError::InvalidAccountData.into());
+    }
+
+    Ok(Address::new_from_array(
+        data[..size_of::<Address>()].try_into().unwrap(),
+    ))
+}
+
+// 转账前校验 authority 是源 token account 的 owner 或 delegate
+// 在 CPI 之前给出明确的错误
+pub fn check_transfer_authority(source: &AccountView, authority: &AccountView) -> ProgramResult {
+    // owner 和 delegate 都要能读到, 数据更短的账户不是 token account
+    if source.data_len() < TOKEN_ACCOUNT_DELEGATE_OFFSET + 36 {
+        return Err(EscrowError::InvalidAccountData.into());
+    }
+
+    if read_token_owner(source)?.eq(authority.address()) {
+        return Ok(());
+    }
+
+    // delegate 是 COption, tag 为 1 时才有值
+    let data = source.try_borrow()?;
+    let delegate = &data[TOKEN_ACCOUNT_DELEGATE_OFFSET..TOKEN_ACCOUNT_DELEGATE_OFFSET + 36];
+    if delegate[0..4] == [1, 0, 0, 0] && delegate[4..36] == *authority.address().as_ref() {
+        return Ok(());
+    }
+
+    Err(EscrowError::InvalidAuthority.into())
+}
+
+// 把 vault 的全部余额转出, 不关闭 vault
+// - token_destination: 接收 vault 中 token 的账户
+// - authority: vault 的 owner (escrow PDA), 通过 signers 签名
+pub fn drain_vault(
+    vault: &AccountView,
+    token_destination: &AccountView,
+    authority: &AccountView,
+    signers: &[Signer],
+) -> ProgramResult {
+    check_transfer_authority(vault, authority)?;
+    let amount = read_token_amount(vault)?;
+
+    Transfer {
+        from: vault,
+        to: token_destination,
+        authority,
+        amount,
+    }
+    .invoke_signed(signers)
+}
+
+// 把 vault 的全部余额转出, 然后关闭 vault
+// Take 和 Refund 都是这个流程, 只是 token 和租金的去向不同
+// - token_destination: 接收 vault 中 token 的账户
+// - rent_destination: 接收 vault 租金的账户
+// - authority: vault 的 owner (escrow PDA), 通过 signers 签名
+pub fn drain_and_close_vault(
+    vault: &AccountView,
+    token_destination: &AccountView,
+    rent_destination: &AccountView,
+    authority: &AccountView,
+    signers: &[Signer],
+) -> ProgramResult {
+    drain_vault(vault, token_destination, authority, signers)?;
+
+    // 转出全部 token 之后, vault 的 lamports 应该正好等于 rent exempt 的最小值
+    // 多出来的 lamports 可能是有人直接转进来的, 也可能是 wSOL vault 没有同步的余额
+    // (wSOL 的 amount 只在 SyncNative 之后才包含直接转入的 lamports, 没同步的部分不会被 Transfer 转出)
+    // CloseAccount 会把 vault 的全部 lamports 转给 rent_destination, 这里明确记录下来
+    let minimum = Rent::get()?.try_minimum_balance(vault.data_len())?;
+    let extra = vault.lamports().saturating_sub(minimum);
+    if extra > 0 {
+        let mut logger = Logger::<64>::default();
+        logger
+            .append("vault extra lamports to rent destination: ")
+            .append(extra);
+        logger.log();
+    }
+
+    // 这里关闭的是 token 账户, 他的 owner 是 token program
+    // 所以这里通过 CPI 调用 CloseAccount 方法, 通过 token program 来关闭 token account
+    // 并且通过 escrow pda 账户的签名证明有权关闭
+    CloseAccount {
+        account: vault,
+        destination: rent_destination,
+        authority,
+    }
+    .invoke_signed(signers)
+}
+
+// 检查 token account 是否被冻结
+// 被冻结的账户既不能转出也不能转入
+pub fn is_frozen_token_account(account: &AccountView) -> Result<bool, ProgramError> {
+    let data = account.try_borrow()?;
+
+    if data.len() <= TOKEN_ACCOUNT_STATE_OFFSET {
+        return Err(EscrowError::InvalidAccountData.into());
+    }
+
+    Ok(data[TOKEN_ACCOUNT_STATE_OFFSET] == TOKEN_ACCOUNT_STATE_FROZEN)
+}
+
+// 检查 mint 是否设置了 DefaultAccountState = Frozen
+// 这种 mint 新创建的 token account 默认是冻结状态, 无法接收转账
+// 只有 token 2022 的 mint 才可能有这个 extension
+pub fn is_default_frozen_mint(mint: &AccountView) -> Result<bool, ProgramError> {
+    if !mint.owned_by(&TOKEN_2022_PROGRAM_ID) {
+        return Ok(false);
+    }
+
+    let data = mint.try_borrow()?;
+
+    Ok(
+        find_token_2022_extension(&data, TOKEN_2022_DEFAULT_ACCOUNT_STATE_EXTENSION)
+            .is_some_and(|value| value.first() == Some(&TOKEN_ACCOUNT_STATE_FROZEN)),
+    )
+}
 
 // mint 账户校验
 // token program 分为两种:
@@ -100,34 +487,39 @@ pub struct MintInterface;
 
 impl AccountCheck for MintInterface {
     fn check(account: &AccountView) -> Result<(), ProgramError> {
-        // 检查 account 是否被 token 2022 program 程序所拥有
-        if account.owned_by(&TOKEN_2022_PROGRAM_ID) {
-            // 获取账户的数据引用
-            let data = account.try_borrow()?;
-
-            // 如果账户数据长度和 spl token mint 账户的长度一样则通过
-            // 否则进行下一步验证
-            if data.len().ne(&pinocchio_token::state::Mint::LEN) {
-                // 如果小于偏移量则返回错误
-                if data.len() < TOKEN_2022_ACCOUNT_DISCRIMINATOR_OFFSET {
-                    return Err(EscrowError::InvalidAccountData)?;
-                }
-                // 检查 account 是否是 mint 账户
-                if data[TOKEN_2022_ACCOUNT_DISCRIMINATOR_OFFSET].ne(&TOKEN_2022_MINT_DISCRIMINATOR)
-                {
-                    return Err(EscrowError::InvalidAccountData.into());
-                }
-            }
-        } else {
-            // 检查 account 是否被 spl token program 所拥有
-            if !account.owned_by(&pinocchio_token::ID) {
-                return Err(EscrowError::InvalidOwner.into());
-            }
-
+        // 绝大多数 mint 是 spl token mint, 先检查 spl token program, 命中时省去后面的判断
+        // 这里不用 owned_by_any: 两个 owner 之后的校验不一样, 用了之后仍然要再区分一次 owner
+        if account.owned_by(&pinocchio_token::ID) {
             // 检查 account 是否是 mint 账户, 通过账户数据长度判断
+            // 这里故意要求长度严格等于 Mint::LEN, 不接受多分配了空间的 mint:
+            // spl token program 自己在解析 mint 时也要求长度完全一致,
+            // 长度不对的账户无法被 token program 当作 mint 使用
             if account.data_len().ne(&pinocchio_token::state::Mint::LEN) {
                 return Err(EscrowError::InvalidAccountData.into());
             }
+
+            return Ok(());
+        }
+
+        // 不是 spl token program, 则必须被 token 2022 program 拥有
+        if !account.owned_by(&TOKEN_2022_PROGRAM_ID) {
+            return Err(EscrowError::InvalidOwner.into());
+        }
+
+        // 获取账户的数据引用
+        let data = account.try_borrow()?;
+
+        // 如果账户数据长度和 spl token mint 账户的长度一样则通过
+        // 否则进行下一步验证
+        if data.len().ne(&pinocchio_token::state::Mint::LEN) {
+            // 如果不足以包含判别器则返回错误
+            if data.len().le(&TOKEN_2022_ACCOUNT_DISCRIMINATOR_OFFSET) {
+                return Err(EscrowError::InvalidAccountData.into());
+            }
+            // 检查 account 是否是 mint 账户
+            if data[TOKEN_2022_ACCOUNT_DISCRIMINATOR_OFFSET].ne(&TOKEN_2022_MINT_DISCRIMINATOR) {
+                return Err(EscrowError::InvalidAccountData.into());
+            }
         }
 
         Ok(())
@@ -139,19 +531,19 @@ pub struct TokenAccountInterface;
 
 impl AccountCheck for TokenAccountInterface {
     fn check(account: &AccountView) -> Result<(), ProgramError> {
+        // 必须被 spl token program 或 token 2022 program 拥有
+        if !owned_by_any(account, &TOKEN_PROGRAM_IDS) {
+            return Err(EscrowError::InvalidOwner.into());
+        }
+
         // 检查是否由 Token-2022 Program 拥有
         if !account.owned_by(&TOKEN_2022_PROGRAM_ID) {
-            // 如果不是 Token-2022，检查是否是旧版 Token Program
-            if !account.owned_by(&pinocchio_token::ID) {
-                return Err(EscrowError::InvalidOwner.into());
-            } else {
-                // 旧版 Token Account 长度验证
-                if account
-                    .data_len()
-                    .ne(&pinocchio_token::state::TokenAccount::LEN)
-                {
-                    return Err(EscrowError::InvalidAccountData.into());
-                }
+            // 旧版 Token Account 长度验证
+            if account
+                .data_len()
+                .ne(&pinocchio_token::state::TokenAccount::LEN)
+            {
+                return Err(EscrowError::InvalidAccountData.into());
             }
         } else {
             // Token-2022 Token Account 验证
@@ -171,6 +563,28 @@ impl AccountCheck for TokenAccountInterface {
             }
         }
 
+        // strict-ata 模式: token account 必须是它自己记录的 owner 和 mint 的 ATA
+        // 普通的 token account (比如用 InitializeAccount 创建的) 会被拒绝
+        #[cfg(feature = "strict-ata")]
+        {
+            let data = account.try_borrow()?;
+            let token_program = if account.owned_by(&TOKEN_2022_PROGRAM_ID) {
+                &TOKEN_2022_PROGRAM_ID
+            } else {
+                &pinocchio_token::ID
+            };
+
+            // token account 数据中 mint 在 [0..32], owner 在 [32..64]
+            let (ata, _bump) = Address::find_program_address(
+                &[&data[32..64], token_program.as_ref(), &data[0..32]],
+                &pinocchio_associated_token_account::ID,
+            );
+
+            if ata.ne(account.address()) {
+                return Err(EscrowError::InvalidAddress.into());
+            }
+        }
+
         Ok(())
     }
 }
@@ -212,6 +626,47 @@ impl AssociatedTokenAccountCheck for AssociatedTokenAccount {
     }
 }
 
+impl AssociatedTokenAccount {
+    // 已知 bump 时计算 ATA 地址, 用 create_program_address 代替 find_program_address
+    // 适用于 wallet 是 PDA 并且 bump 已经保存的情况 (比如 escrow 的 vault)
+    // 传入的 wallet 直接使用, 不会重新派生 wallet 自己的地址
+    pub fn derive_with_bump(
+        wallet: &Address,
+        mint: &Address,
+        token_program: &Address,
+        bump: u8,
+    ) -> Result<Address, ProgramError> {
+        Address::create_program_address(
+            &[
+                wallet.as_ref(),
+                token_program.as_ref(),
+                mint.as_ref(),
+                &[bump],
+            ],
+            &pinocchio_associated_token_account::ID,
+        )
+        .map_err(|_| EscrowError::InvalidAddress.into())
+    }
+
+    // 和 AssociatedTokenAccountCheck::check 一样, 但是使用已知的 bump
+    pub fn check_with_bump(
+        account: &AccountView,
+        wallet: &Address,
+        mint: &AccountView,
+        token_program: &AccountView,
+        bump: u8,
+    ) -> ProgramResult {
+        TokenAccountInterface::check(account)?;
+
+        let ata = Self::derive_with_bump(wallet, mint.address(), token_program.address(), bump)?;
+        if ata.ne(account.address()) {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        Ok(())
+    }
+}
+
 // 创建 ATA 账户
 impl AssociatedTokenAccountInit for AssociatedTokenAccount {
     // 创建新的 ATA
@@ -229,6 +684,13 @@ impl AssociatedTokenAccountInit for AssociatedTokenAccount {
         system_program: &AccountView,
         token_program: &AccountView,
     ) -> ProgramResult {
+        // 提前检查 payer 是否付得起 ATA 的租金, 给出明确的错误
+        // token 2022 的 ATA 可能带有 extension, 实际需要的更多, 这里只检查最小值
+        check_min_lamports(
+            payer,
+            Rent::get()?.try_minimum_balance(pinocchio_token::state::TokenAccount::LEN)?,
+        )?;
+
         Create {
             funding_account: payer, // 支付创建费用的账户
             account,                // 要创建的 ATA 账户
@@ -237,7 +699,10 @@ impl AssociatedTokenAccountInit for AssociatedTokenAccount {
             system_program,         // System Program
             token_program,          // Token Program
         }
-        .invoke()
+        .invoke()?;
+
+        // 后置检查: ATA program 创建的账户必须是 rent exempt 的
+        check_rent_exempt(account)
     }
 
     // 如果账户不存在则创建
@@ -247,6 +712,7 @@ impl AssociatedTokenAccountInit for AssociatedTokenAccount {
     // 1. 先尝试验证账户是否存在且正确
     // 2. 如果验证通过，说明账户已存在，直接返回
     // 3. 如果验证失败，说明账户不存在，创建新账户
+    // 4. 创建之后再验证一次, 确保 ATA program 创建出来的确实是预期的账户
     fn init_if_needed(
         account: &AccountView,
         mint: &AccountView,
@@ -257,8 +723,95 @@ impl AssociatedTokenAccountInit for AssociatedTokenAccount {
     ) -> ProgramResult {
         match Self::check(account, owner, mint, token_program) {
             Ok(_) => Ok(()), // 账户已存在且正确，跳过创建
-            Err(_) => Self::init(account, mint, payer, owner, system_program, token_program), // 创建账户
+            Err(_) => {
+                // 创建账户
+                Self::init(account, mint, payer, owner, system_program, token_program)?;
+                Self::check(account, owner, mint, token_program)
+            }
+        }
+    }
+}
+
+// 构造 escrow PDA 的签名种子 [b"escrow", maker, seed_le, bump]
+// Make, Take, Refund 等指令统一用它, 保证种子的顺序和 Escrow::SEED 一致
+// 参数都由调用方持有, 返回的 Seed 借用它们, 所以调用方需要先把 seed 和 bump 绑定到局部变量
+#[inline(always)]
+pub fn escrow_signer<'a>(
+    maker: &'a Address,
+    seed_le: &'a [u8; 8],
+    bump: &'a [u8; 1],
+) -> [Seed<'a>; 4] {
+    [
+        Seed::from(crate::state::Escrow::SEED),
+        Seed::from(maker.as_ref()),
+        Seed::from(seed_le),
+        Seed::from(bump),
+    ]
+}
+
+// 可变借用 escrow 账户并映射为 Escrow, 在闭包中修改
+// Escrow::load_mut 只检查长度, 这里在借用之前还会检查:
+// - 账户由本程序拥有
+// - 账户可写
+// 闭包返回之后借用守卫立即释放, 之后的 CPI 可以再借用这个账户
+// Escrow 没有版本字段, 也没有 "已关闭" 的标记状态:
+// 关闭之后账户归还给 system program 且数据清空, 所以在 owner 检查这一步就会被拒绝
+pub fn load_checked_mut<R>(
+    account: &AccountView,
+    f: impl FnOnce(&mut crate::state::Escrow) -> R,
+) -> Result<R, ProgramError> {
+    if !account.owned_by(&crate::ID) {
+        return Err(EscrowError::InvalidOwner.into());
+    }
+
+    WritableAccount::check(account)?;
+
+    let mut data = account.try_borrow_mut()?;
+    Ok(f(crate::state::Escrow::load_mut(data.as_mut())?))
+}
+
+// 一次指令中需要多次用到的派生地址
+// 在 try_from 中计算一次, 然后传给 process, 避免重复调用 find_program_address
+pub struct DerivedAddresses {
+    // escrow PDA 地址
+    pub escrow: Address,
+    // escrow PDA 的 bump
+    pub escrow_bump: u8,
+    // vault ATA 地址 (owner 是 escrow PDA)
+    pub vault: Address,
+    // vault ATA 的 bump
+    pub vault_bump: u8,
+}
+
+impl DerivedAddresses {
+    // escrow 和 vault 都使用 escrow 中存储的 bump, 所以用更便宜的 create_program_address
+    // 派生出的 escrow 地址必须和传入的 escrow 账户一致, 否则返回 InvalidEscrowPda
+    // 这个检查在派生 vault 之前: maker 或 bump 不对时, 用错误的 escrow 地址派生 vault 也可能失败,
+    // 那样返回的是 vault 的 InvalidAddress, 掩盖了真正的原因
+    // escrow 的地址由 maker 派生, 地址一致也就证明了 maker 是 escrow 的创建者
+    pub fn derive(
+        escrow_account: &Address,
+        maker: &Address,
+        seed: u64,
+        escrow_bump: u8,
+        mint_a: &Address,
+        token_program: &Address,
+        vault_bump: u8,
+    ) -> Result<Self, ProgramError> {
+        let escrow = crate::state::Escrow::derive_address(maker, seed, escrow_bump)?;
+        if escrow.ne(escrow_account) {
+            return Err(EscrowError::InvalidEscrowPda.into());
         }
+
+        let vault =
+            AssociatedTokenAccount::derive_with_bump(&escrow, mint_a, token_program, vault_bump)?;
+
+        Ok(Self {
+            escrow,
+            escrow_bump,
+            vault,
+            vault_bump,
+        })
     }
 }
 
@@ -273,6 +826,10 @@ impl AccountCheck for ProgramAccount {
             return Err(EscrowError::InvalidOwner.into());
         }
 
+        // 注意: 无法区分 "escrow 已经被消费" 和 "从来没有创建过"
+        // AccountClose::close 最后调用 account.close(), owner, lamports 和数据长度都会被清零
+        // 被 Take / Refund 关闭的 escrow 变成 system program 拥有的空账户, 和没有创建过的地址一样, 在上面返回 InvalidOwner
+
         // 验证账户数据长度是否匹配 Escrow 结构体
         if account.data_len().ne(&crate::state::Escrow::LEN) {
             return Err(EscrowError::InvalidAccountData.into());
@@ -282,10 +839,72 @@ impl AccountCheck for ProgramAccount {
     }
 }
 
+// 验证 stats 账户
+pub struct StatsAccount;
+
+impl AccountCheck for StatsAccount {
+    fn check(account: &AccountView) -> Result<(), ProgramError> {
+        if !account.owned_by(&crate::ID) {
+            return Err(EscrowError::InvalidOwner.into());
+        }
+
+        if account.data_len().ne(&crate::state::Stats::LEN) {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        // 必须是 [b"stats"] PDA, 否则任意一个大小相同的本程序账户都会被当作 stats 更新
+        // 用账户中保存的 bump 校验, 不需要 find_program_address
+        let bump = crate::state::Stats::load(&account.try_borrow()?)?.bump[0];
+        let stats =
+            Address::create_program_address(&[crate::state::Stats::SEED, &[bump]], &crate::ID)
+                .map_err(|_| EscrowError::InvalidAddress)?;
+        if account.address().ne(&stats) {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        // stats 账户每次都会被更新
+        WritableAccount::check(account)?;
+
+        Ok(())
+    }
+}
+
+// 验证 escrow 的元数据账户
+// 地址用账户中保存的 bump 和 escrow 地址校验
+pub struct EscrowMetaAccount;
+
+impl EscrowMetaAccount {
+    pub fn check(account: &AccountView, escrow: &AccountView) -> ProgramResult {
+        if !account.owned_by(&crate::ID) {
+            return Err(EscrowError::InvalidOwner.into());
+        }
+
+        let bump = crate::state::EscrowMeta::bump(&account.try_borrow()?)?;
+        let address = Address::create_program_address(
+            &[
+                crate::state::EscrowMeta::SEED,
+                escrow.address().as_ref(),
+                &[bump],
+            ],
+            &crate::ID,
+        )
+        .map_err(|_| EscrowError::InvalidAddress)?;
+        if address.ne(account.address()) {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        // 元数据账户会和 escrow 一起关闭
+        WritableAccount::check(account)?;
+
+        Ok(())
+    }
+}
+
 // 创建程序账户
 pub trait ProgramAccountInit {
     // 创建程序拥有的 PDA 账户
-    // T 用于自动推导账户所需的空间大小，调用时使用 turbo fish 语法：init::<Escrow>(...) ❌ 行不通
+    // 空间大小由调用方显式传入 (比如 Escrow::LEN), 而不是泛型的 size_of::<T>()
+    // 元数据账户是变长的, 没有对应的结构体; 固定大小的账户的 LEN 和 size_of 一致 (见 state.rs 中的编译期断言)
     fn init<'a>(
         payer: &AccountView,   // 支付者（对应 payer = xxx）
         account: &AccountView, // 要创建的账户
@@ -301,11 +920,10 @@ impl ProgramAccountInit for ProgramAccount {
         seeds: &[Seed<'a>],
         space: usize,
     ) -> ProgramResult {
-        // 通过泛型 T 自动计算账户所需的空间大小, ❌ 行不通
-        // let space = core::mem::size_of::<T>();
-
         // 获取租金豁免所需的 lamports 数量
         // 对应 Anchor 自动进行的租金计算
+        // 程序中所有的租金计算都通过 Rent::get() (sol_get_rent_sysvar syscall) 获取, 不接受 Rent sysvar 账户
+        // 如果以后需要从账户读取, 必须先校验账户地址是 Rent sysvar 的 id, 不能信任调用方传入的账户
         let lamports = Rent::get()?.try_minimum_balance(space)?;
 
         // 使用种子创建 PDA 签名者
@@ -323,6 +941,11 @@ impl ProgramAccountInit for ProgramAccount {
         }
         .invoke_signed(&signer)?; // 使用 PDA 签名调用
 
+        // 数据长度必须正好是请求的 space, 否则之后的 load 会失败
+        if account.data_len() != space {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
         Ok(())
     }
 }
@@ -337,6 +960,10 @@ impl AccountClose for ProgramAccount {
     // 只有账户的 owner 才能 减少 lamports
     // 交易结束后, 运行时会检查所有账户的 lamports 总和, 必须和交易前一致, 否则交易失败, 所以不能凭空增加 lamports
     fn close(account: &AccountView, destination: &AccountView) -> ProgramResult {
+        // 接收 lamports 的账户必须是可写的
+        // 否则要等到指令结束时运行时才报错, 提前返回明确的错误
+        WritableAccount::check(destination)?;
+
         {
             // 将账户数据的第一个字节设置为 0xff
             // 这是 Solana 的惯例，表示账户已关闭
@@ -356,3 +983,515 @@ impl AccountClose for ProgramAccount {
         account.close()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{ata_address, escrow_address, token_account_data, TestAccount};
+
+    // token 2022 mint 的数据: 补齐到 token account 的长度, 然后是 AccountType 和 extension
+    fn token_2022_mint_data(extensions: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0u8; TOKEN_2022_EXTENSIONS_OFFSET];
+        data[TOKEN_2022_ACCOUNT_DISCRIMINATOR_OFFSET] = 1;
+        for (ty, value) in extensions {
+            data.extend_from_slice(&ty.to_le_bytes());
+            data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    #[test]
+    fn find_extension() {
+        // 第一个 extension 之后的也能找到
+        let data = token_2022_mint_data(&[
+            (1, &[7; 3]),
+            (TOKEN_2022_DEFAULT_ACCOUNT_STATE_EXTENSION, &[2]),
+        ]);
+        assert_eq!(find_token_2022_extension(&data, 1), Some(&[7u8; 3][..]));
+        assert_eq!(
+            find_token_2022_extension(&data, TOKEN_2022_DEFAULT_ACCOUNT_STATE_EXTENSION),
+            Some(&[2u8][..])
+        );
+        assert_eq!(find_token_2022_extension(&data, 3), None);
+
+        // 长度超出账户数据的 extension 被忽略
+        let mut data = token_2022_mint_data(&[(1, &[7; 3])]);
+        data.truncate(data.len() - 1);
+        assert_eq!(find_token_2022_extension(&data, 1), None);
+
+        // 没有 extension 的 mint
+        let data = [0u8; pinocchio_token::state::Mint::LEN];
+        assert_eq!(find_token_2022_extension(&data, 1), None);
+    }
+
+    #[test]
+    fn default_frozen_mint() {
+        let address = Address::new_from_array([1; 32]);
+        let frozen = token_2022_mint_data(&[(
+            TOKEN_2022_DEFAULT_ACCOUNT_STATE_EXTENSION,
+            &[TOKEN_ACCOUNT_STATE_FROZEN],
+        )]);
+        let initialized =
+            token_2022_mint_data(&[(TOKEN_2022_DEFAULT_ACCOUNT_STATE_EXTENSION, &[1])]);
+
+        let mut mint = TestAccount::new(address.clone(), TOKEN_2022_PROGRAM_ID, &frozen);
+        assert!(is_default_frozen_mint(&mint.view()).unwrap());
+
+        let mut mint = TestAccount::new(address.clone(), TOKEN_2022_PROGRAM_ID, &initialized);
+        assert!(!is_default_frozen_mint(&mint.view()).unwrap());
+
+        // spl token 的 mint 没有 extension, 即使数据看起来一样也不是
+        let mut mint = TestAccount::new(address, pinocchio_token::ID, &frozen);
+        assert!(!is_default_frozen_mint(&mint.view()).unwrap());
+    }
+
+    #[test]
+    fn deadline_at() {
+        // 0 表示不限制
+        assert!(!deadline_passed_at(0, 1_700_000_000));
+        assert!(!deadline_passed_at(0, i64::MAX));
+
+        // 时钟没有设置时不执行截止时间
+        assert!(!deadline_passed_at(100, 0));
+        assert!(!deadline_passed_at(100, -1));
+
+        assert!(!deadline_passed_at(1_700_000_000, 1_699_999_999));
+        assert!(!deadline_passed_at(1_700_000_000, 1_700_000_000));
+        assert!(deadline_passed_at(1_700_000_000, 1_700_000_001));
+    }
+
+    // 带有 delegate 的 token account 数据
+    fn delegated_token_account_data(owner: &Address, delegate: Option<&Address>) -> Vec<u8> {
+        let mut data = token_account_data(&Address::default(), owner, 0);
+        if let Some(delegate) = delegate {
+            data[TOKEN_ACCOUNT_DELEGATE_OFFSET] = 1;
+            data[TOKEN_ACCOUNT_DELEGATE_OFFSET + 4..TOKEN_ACCOUNT_DELEGATE_OFFSET + 36]
+                .copy_from_slice(delegate.as_ref());
+        }
+        data
+    }
+
+    #[test]
+    fn transfer_authority() {
+        let owner = Address::new_from_array([1; 32]);
+        let delegate = Address::new_from_array([2; 32]);
+        let mut owner = TestAccount::empty(owner);
+        let mut delegate = TestAccount::empty(delegate);
+        let mut other = TestAccount::empty(Address::new_from_array([3; 32]));
+        let (owner, delegate, other) = (owner.view(), delegate.view(), other.view());
+        let address = Address::new_from_array([4; 32]);
+
+        let data = delegated_token_account_data(owner.address(), Some(delegate.address()));
+        let mut source = TestAccount::new(address.clone(), pinocchio_token::ID, &data);
+        let source = source.view();
+        assert!(check_transfer_authority(&source, &owner).is_ok());
+        assert!(check_transfer_authority(&source, &delegate).is_ok());
+        assert_eq!(
+            check_transfer_authority(&source, &other).err(),
+            Some(EscrowError::InvalidAuthority.into())
+        );
+
+        // tag 为 0 时 delegate 没有值, 即使后面的字节和 authority 一样
+        let mut data = delegated_token_account_data(owner.address(), Some(delegate.address()));
+        data[TOKEN_ACCOUNT_DELEGATE_OFFSET] = 0;
+        let mut source = TestAccount::new(address.clone(), pinocchio_token::ID, &data);
+        assert_eq!(
+            check_transfer_authority(&source.view(), &delegate).err(),
+            Some(EscrowError::InvalidAuthority.into())
+        );
+
+        // 数据太短, 读不到 delegate, 即使 owner 一致也拒绝
+        let data = delegated_token_account_data(owner.address(), None);
+        let mut source = TestAccount::new(
+            address,
+            pinocchio_token::ID,
+            &data[..TOKEN_ACCOUNT_DELEGATE_OFFSET + 35],
+        );
+        assert_eq!(
+            check_transfer_authority(&source.view(), &owner).err(),
+            Some(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    // vault 是 mint_a 合法的 ATA, 但是 wallet 是另一个 PDA (同一个 maker 的另一个 escrow)
+    // 地址校验和转账前的 authority 校验都会拒绝它
+    #[test]
+    fn vault_of_other_escrow() {
+        let maker = Address::new_from_array([1; 32]);
+        let mint_a = Address::new_from_array([2; 32]);
+        let (escrow, bump) = escrow_address(&maker, 1);
+        let (other_escrow, _) = escrow_address(&maker, 2);
+        let (vault, vault_bump) = ata_address(&escrow, &mint_a);
+        let (other_vault, _) = ata_address(&other_escrow, &mint_a);
+
+        let derived = DerivedAddresses::derive(
+            &escrow,
+            &maker,
+            1,
+            bump,
+            &mint_a,
+            &pinocchio_token::ID,
+            vault_bump,
+        )
+        .unwrap();
+        assert_eq!(derived.vault, vault);
+        assert_ne!(derived.vault, other_vault);
+
+        let mut authority = TestAccount::empty(escrow.clone());
+        let authority = authority.view();
+        let mut other = TestAccount::new(
+            other_vault,
+            pinocchio_token::ID,
+            &token_account_data(&mint_a, &other_escrow, 100),
+        );
+        assert_eq!(
+            check_transfer_authority(&other.view(), &authority).err(),
+            Some(EscrowError::InvalidAuthority.into())
+        );
+
+        // 即使在 vault 的地址上, owner 不是 escrow 的 token account 也会在 CPI 之前被拒绝
+        let mut forged = TestAccount::new(
+            vault.clone(),
+            pinocchio_token::ID,
+            &token_account_data(&mint_a, &other_escrow, 100),
+        );
+        assert_eq!(
+            check_transfer_authority(&forged.view(), &authority).err(),
+            Some(EscrowError::InvalidAuthority.into())
+        );
+
+        let mut vault = TestAccount::new(
+            vault,
+            pinocchio_token::ID,
+            &token_account_data(&mint_a, &escrow, 100),
+        );
+        assert!(check_transfer_authority(&vault.view(), &authority).is_ok());
+    }
+
+    #[test]
+    fn distinct_accounts() {
+        let mut a = TestAccount::empty(Address::new_from_array([1; 32]));
+        let mut b = TestAccount::empty(Address::new_from_array([2; 32]));
+        let mut c = TestAccount::empty(Address::new_from_array([3; 32]));
+        let (a, b, c) = (a.view(), b.view(), c.view());
+
+        assert!(check_distinct(&[&a, &b, &c]).is_ok());
+        assert!(check_distinct(&[]).is_ok());
+
+        // 同一个账户在任意位置重复出现都会被拒绝
+        for accounts in [[&a, &a, &c], [&a, &b, &a], [&a, &c, &c]] {
+            assert_eq!(
+                check_distinct(&accounts).err(),
+                Some(EscrowError::InvalidAddress.into())
+            );
+        }
+    }
+
+    // 按照索引器的方式解析事件日志, 检查每个字段
+    #[cfg(feature = "indexer-logs")]
+    #[test]
+    fn event_line_is_parseable() {
+        let line = event_line("take", u64::MAX, 1_000);
+        let line = core::str::from_utf8(&line).unwrap();
+
+        let body = line.strip_prefix("ESCROW_EVENT ").unwrap();
+        let body = body.strip_prefix('{').unwrap().strip_suffix('}').unwrap();
+        let fields: Vec<(&str, &str)> = body
+            .split(',')
+            .map(|field| field.split_once(':').unwrap())
+            .collect();
+
+        assert_eq!(
+            fields,
+            [
+                ("\"kind\"", "\"take\""),
+                ("\"seed\"", "18446744073709551615"),
+                ("\"amount\"", "1000"),
+            ]
+        );
+    }
+
+    // 用保存的 bump 校验 PDA (escrow) 拥有的 ATA
+    #[test]
+    fn ata_check_with_bump() {
+        let (escrow, _) = escrow_address(&Address::new_from_array([1; 32]), 7);
+        let mint_address = Address::new_from_array([2; 32]);
+        let (vault, bump) = ata_address(&escrow, &mint_address);
+        let mut mint = TestAccount::new(
+            mint_address.clone(),
+            pinocchio_token::ID,
+            &[0; pinocchio_token::state::Mint::LEN],
+        );
+        let mut token_program = TestAccount::empty(pinocchio_token::ID);
+        let (mint, token_program) = (mint.view(), token_program.view());
+        let data = token_account_data(&mint_address, &escrow, 0);
+
+        let mut account = TestAccount::new(vault, pinocchio_token::ID, &data);
+        assert!(AssociatedTokenAccount::check_with_bump(
+            &account.view(),
+            &escrow,
+            &mint,
+            &token_program,
+            bump
+        )
+        .is_ok());
+
+        // 同样合法的 token account, 但不在 escrow 的 ATA 地址上
+        let mut other =
+            TestAccount::new(Address::new_from_array([9; 32]), pinocchio_token::ID, &data);
+        assert_eq!(
+            AssociatedTokenAccount::check_with_bump(
+                &other.view(),
+                &escrow,
+                &mint,
+                &token_program,
+                bump
+            )
+            .err(),
+            Some(EscrowError::InvalidAddress.into())
+        );
+
+        // 错误的 bump 派生出其他地址, 或者落在曲线上无法派生
+        assert_eq!(
+            AssociatedTokenAccount::check_with_bump(
+                &account.view(),
+                &escrow,
+                &mint,
+                &token_program,
+                bump.wrapping_sub(1)
+            )
+            .err(),
+            Some(EscrowError::InvalidAddress.into())
+        );
+    }
+
+    #[test]
+    fn frozen_token_account() {
+        let address = Address::new_from_array([1; 32]);
+        let mint = Address::new_from_array([2; 32]);
+        let mut data = token_account_data(&mint, &address, 0);
+
+        let mut initialized = TestAccount::new(address.clone(), pinocchio_token::ID, &data);
+        assert_eq!(is_frozen_token_account(&initialized.view()), Ok(false));
+
+        data[TOKEN_ACCOUNT_STATE_OFFSET] = TOKEN_ACCOUNT_STATE_FROZEN;
+        let mut frozen = TestAccount::new(address.clone(), pinocchio_token::ID, &data);
+        assert_eq!(is_frozen_token_account(&frozen.view()), Ok(true));
+
+        // 数据不足以包含 state 字段
+        let mut short = TestAccount::new(
+            address,
+            pinocchio_token::ID,
+            &data[..TOKEN_ACCOUNT_STATE_OFFSET],
+        );
+        assert_eq!(
+            is_frozen_token_account(&short.view()).err(),
+            Some(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    #[test]
+    fn program_account_validation() {
+        for token_program in TOKEN_PROGRAM_IDS {
+            let mut account = TestAccount::empty(token_program);
+            assert!(validate_token_program(&account.view()).is_ok());
+        }
+        let mut ata_program = TestAccount::empty(pinocchio_associated_token_account::ID);
+        assert!(validate_associated_token_program(&ata_program.view()).is_ok());
+
+        // 两个校验互相不接受对方的程序, 也不接受 system program
+        let mut system_program = TestAccount::empty(pinocchio_system::ID);
+        for account in [&mut ata_program, &mut system_program] {
+            assert_eq!(
+                validate_token_program(&account.view()).err(),
+                Some(EscrowError::InvalidTokenProgram.into())
+            );
+        }
+        let mut token_program = TestAccount::empty(pinocchio_token::ID);
+        for account in [&mut token_program, &mut system_program] {
+            assert_eq!(
+                validate_associated_token_program(&account.view()).err(),
+                Some(ProgramError::IncorrectProgramId)
+            );
+        }
+    }
+
+    #[test]
+    fn writable_account_check() {
+        let address = Address::new_from_array([1; 32]);
+
+        let mut writable = TestAccount::empty(address.clone()).writable();
+        assert!(WritableAccount::check(&writable.view()).is_ok());
+
+        // 签名不代表可写
+        let mut readonly = TestAccount::empty(address).signer();
+        assert_eq!(
+            WritableAccount::check(&readonly.view()).err(),
+            Some(ProgramError::Immutable)
+        );
+    }
+
+    // Rent::get() 在测试中无法使用, 这里直接用主网上 165 字节 token account 的租金
+    #[test]
+    fn payer_covers_ata_rent() {
+        const TOKEN_ACCOUNT_RENT: u64 = 2_039_280;
+
+        let mut payer =
+            TestAccount::empty(Address::new_from_array([1; 32])).lamports(TOKEN_ACCOUNT_RENT);
+        assert!(check_min_lamports(&payer.view(), TOKEN_ACCOUNT_RENT).is_ok());
+
+        let mut payer =
+            TestAccount::empty(Address::new_from_array([1; 32])).lamports(TOKEN_ACCOUNT_RENT - 1);
+        assert_eq!(
+            check_min_lamports(&payer.view(), TOKEN_ACCOUNT_RENT).err(),
+            Some(EscrowError::NotEnoughRentExempt.into())
+        );
+    }
+
+    #[test]
+    fn min_lamports() {
+        let mut account = TestAccount::empty(Address::new_from_array([1; 32])).lamports(2_039_280);
+        let account = account.view();
+
+        assert!(check_min_lamports(&account, 0).is_ok());
+        assert!(check_min_lamports(&account, 2_039_280).is_ok());
+        assert_eq!(
+            check_min_lamports(&account, 2_039_281).err(),
+            Some(EscrowError::NotEnoughRentExempt.into())
+        );
+    }
+
+    // 不存在的账户: system program 拥有并且没有数据
+    #[test]
+    fn uninitialized_account_check() {
+        let address = Address::new_from_array([1; 32]);
+
+        let mut empty = TestAccount::empty(address.clone());
+        assert!(UninitializedAccount::check(&empty.view()).is_ok());
+
+        // 有数据的 system 账户, 以及已经被其他程序创建的账户
+        let mut with_data = TestAccount::new(address.clone(), pinocchio_system::ID, &[0; 8]);
+        let mut created = TestAccount::new(address, crate::ID, &[]);
+        for account in [&mut with_data, &mut created] {
+            assert_eq!(
+                UninitializedAccount::check(&account.view()).err(),
+                Some(ProgramError::AccountAlreadyInitialized)
+            );
+        }
+    }
+
+    #[test]
+    fn owned_by_any_programs() {
+        let address = Address::new_from_array([1; 32]);
+        let mut legacy = TestAccount::new(address.clone(), pinocchio_token::ID, &[]);
+        let mut token_2022 = TestAccount::new(address.clone(), TOKEN_2022_PROGRAM_ID, &[]);
+        let mut system = TestAccount::empty(address.clone());
+        let mut program = TestAccount::new(address, crate::ID, &[]);
+
+        assert!(owned_by_any(&legacy.view(), &TOKEN_PROGRAM_IDS));
+        assert!(owned_by_any(&token_2022.view(), &TOKEN_PROGRAM_IDS));
+        assert!(!owned_by_any(&system.view(), &TOKEN_PROGRAM_IDS));
+        assert!(!owned_by_any(&program.view(), &TOKEN_PROGRAM_IDS));
+
+        // 只列出其中一个程序, 以及空列表
+        assert!(!owned_by_any(&token_2022.view(), &[pinocchio_token::ID]));
+        assert!(owned_by_any(
+            &program.view(),
+            &[pinocchio_token::ID, crate::ID]
+        ));
+        assert!(!owned_by_any(&legacy.view(), &[]));
+    }
+
+    // MintInterface 的 spl token 快速路径和 token 2022 路径对 owner 的判断与 owned_by_any 一致
+    #[test]
+    fn mint_interface_owner() {
+        let address = Address::new_from_array([1; 32]);
+        let mint = [0u8; pinocchio_token::state::Mint::LEN];
+
+        for owner in TOKEN_PROGRAM_IDS {
+            let mut account = TestAccount::new(address.clone(), owner, &mint);
+            assert!(MintInterface::check(&account.view()).is_ok());
+        }
+
+        let mut account = TestAccount::new(address, crate::ID, &mint);
+        assert_eq!(
+            MintInterface::check(&account.view()).err(),
+            Some(EscrowError::InvalidOwner.into())
+        );
+    }
+
+    #[test]
+    fn load_checked_mut_preconditions() {
+        let address = Address::new_from_array([1; 32]);
+
+        let mut escrow =
+            TestAccount::new(address.clone(), crate::ID, &[0; crate::state::Escrow::LEN])
+                .writable();
+        let escrow = escrow.view();
+        assert_eq!(
+            load_checked_mut(&escrow, |escrow| escrow.set_deadline(7)),
+            Ok(())
+        );
+        assert_eq!(
+            crate::state::Escrow::load(&escrow.try_borrow().unwrap())
+                .unwrap()
+                .deadline,
+            7
+        );
+
+        // 只读的 escrow
+        let mut readonly =
+            TestAccount::new(address.clone(), crate::ID, &[0; crate::state::Escrow::LEN]);
+        assert_eq!(
+            load_checked_mut(&readonly.view(), |_| ()).err(),
+            Some(ProgramError::Immutable)
+        );
+
+        // 其他程序拥有的账户, 以及被关闭之后的 escrow
+        let mut other = TestAccount::new(
+            address.clone(),
+            pinocchio_token::ID,
+            &[0; crate::state::Escrow::LEN],
+        )
+        .writable();
+        let mut closed = TestAccount::empty(address.clone()).writable();
+        for account in [&mut other, &mut closed] {
+            assert_eq!(
+                load_checked_mut(&account.view(), |_| ()).err(),
+                Some(EscrowError::InvalidOwner.into())
+            );
+        }
+
+        // 本程序拥有但长度不对
+        let mut short =
+            TestAccount::new(address, crate::ID, &[0; crate::state::Escrow::LEN - 1]).writable();
+        assert_eq!(
+            load_checked_mut(&short.view(), |_| ()).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn program_account_check() {
+        let address = Address::new_from_array([1; 32]);
+
+        let mut escrow =
+            TestAccount::new(address.clone(), crate::ID, &[0; crate::state::Escrow::LEN]);
+        assert!(ProgramAccount::check(&escrow.view()).is_ok());
+
+        // 没有创建过的地址, 以及被关闭之后的 escrow, 都是 system program 拥有的空账户
+        let mut empty = TestAccount::empty(address.clone());
+        assert_eq!(
+            ProgramAccount::check(&empty.view()).err(),
+            Some(EscrowError::InvalidOwner.into())
+        );
+
+        // 本程序拥有但长度不对, 比如 stats 账户
+        let mut stats = TestAccount::new(address, crate::ID, &[0; crate::state::Stats::LEN]);
+        assert_eq!(
+            ProgramAccount::check(&stats.view()).err(),
+            Some(EscrowError::InvalidAccountData.into())
+        );
+    }
+}
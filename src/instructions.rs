@@ -1,7 +1,23 @@
+pub mod batch_refund;
+pub mod get_escrow;
+pub mod init_stats;
 pub mod make;
 pub mod refund;
+pub mod repair_escrow;
+pub mod set_escrow_meta;
+pub mod simulate_make;
+pub mod split_escrow;
 pub mod take;
+pub mod update_expiry;
 
+pub use batch_refund::*;
+pub use get_escrow::*;
+pub use init_stats::*;
 pub use make::*;
 pub use refund::*;
+pub use repair_escrow::*;
+pub use set_escrow_meta::*;
+pub use simulate_make::*;
+pub use split_escrow::*;
 pub use take::*;
+pub use update_expiry::*;
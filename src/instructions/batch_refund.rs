@@ -0,0 +1,253 @@
+// 批量退款, maker 一次关闭多个 escrow
+use crate::{
+    helpers::{
+        check_distinct, not_enough_account_keys, AccountCheck, InstructionData, StatsAccount,
+    },
+    instructions::{Refund, RefundAccounts},
+    state::Stats,
+};
+use pinocchio::{error::ProgramError, AccountView};
+
+// 一次最多退款的 escrow 数量
+// 每个 escrow 的退款包含 ATA 检查, PDA 派生和 3 次 CPI
+// 限制数量, 保证在单笔交易的 CU 和账户数量限制以内
+pub const MAX_BATCH_REFUND_ESCROWS: usize = 4;
+
+// BatchRefund 指令没有额外的数据
+pub struct BatchRefundInstructionData;
+
+impl InstructionData for BatchRefundInstructionData {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if !data.is_empty() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self)
+    }
+}
+
+// 账户列表由多组 Refund 的账户组成, 每组的顺序和 Refund 完全一样:
+// [maker, escrow, mint_a, vault, maker_ata_a, system_program, token_program, ata_program]
+// 每组都会按照 Refund 的规则独立校验 (包括 maker 签名和 escrow PDA)
+// 所有组在 try_from 中校验完才开始退款, 任意一组失败, 整个交易回滚
+// 每组没有 escrow_meta 的位置, 有元数据账户的 escrow 会返回 MetadataRequired, 需要单独 Refund
+// 可选的 stats 账户放在所有组的后面, 每退款一个 escrow 记录一次
+pub struct BatchRefund<'a> {
+    pub instruction_data: BatchRefundInstructionData,
+    // 校验过的每一组, 从前往后排列
+    pub refunds: [Option<Refund<'a>>; MAX_BATCH_REFUND_ESCROWS],
+    pub stats: Option<&'a AccountView>,
+}
+
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for BatchRefund<'a> {
+    type Error = ProgramError;
+
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
+        let instruction_data = BatchRefundInstructionData::unpack(data)?;
+
+        // 至少要有一组
+        if accounts.len() < RefundAccounts::LEN {
+            return Err(not_enough_account_keys(
+                "batch_refund",
+                RefundAccounts::LEN,
+                accounts.len(),
+            ));
+        }
+
+        // 整数组之后最多多出一个账户, 它是可选的 stats 账户
+        let (accounts, stats) = match accounts.len() % RefundAccounts::LEN {
+            0 => (accounts, None),
+            1 => {
+                let (stats, groups) = accounts.split_last().unwrap();
+                StatsAccount::check(stats)?;
+                (groups, Some(stats))
+            }
+            _ => return Err(ProgramError::InvalidArgument),
+        };
+
+        if accounts.len() / RefundAccounts::LEN > MAX_BATCH_REFUND_ESCROWS {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        // 同一个 escrow 不能出现在两组中
+        let mut escrows: [&AccountView; MAX_BATCH_REFUND_ESCROWS] =
+            [&accounts[1]; MAX_BATCH_REFUND_ESCROWS];
+        let mut refunds: [Option<Refund<'a>>; MAX_BATCH_REFUND_ESCROWS] = Default::default();
+        let groups = accounts.chunks_exact(RefundAccounts::LEN);
+        let count = groups.len();
+        for (i, group) in groups.enumerate() {
+            escrows[i] = &group[1];
+            // 每组只有 Refund 必须的账户, stats 由这里统一记录
+            refunds[i] = Some(Refund::try_from((&[][..], group))?);
+        }
+        check_distinct(&escrows[..count])?;
+
+        Ok(Self {
+            instruction_data,
+            refunds,
+            stats,
+        })
+    }
+}
+
+impl<'a> BatchRefund<'a> {
+    pub const DISCRIMINATOR: &'a u8 = &4;
+
+    pub fn process(&self) -> Result<(), ProgramError> {
+        for refund in self.refunds.iter().flatten() {
+            refund.process()?;
+        }
+
+        if let Some(stats) = self.stats {
+            let mut data = stats.try_borrow_mut()?;
+            let stats = Stats::load_mut(data.as_mut())?;
+            for _ in self.refunds.iter().flatten() {
+                stats.record_refund();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        errors::EscrowError,
+        state::Escrow,
+        test_utils::{
+            ata_address, empty_accounts, escrow_address, token_account_data, views, TestAccount,
+        },
+    };
+    use pinocchio::Address;
+
+    // 一组合法的 Refund 账户, escrow 的 seed 为 seed, 数据中保存的 seed 为 stored_seed
+    // escrow_owner 是 escrow 账户的 owner
+    fn group(seed: u64, stored_seed: u64, escrow_owner: Address) -> Vec<TestAccount> {
+        let maker = Address::new_from_array([1; 32]);
+        let mint_a = Address::new_from_array([2; 32]);
+        let (escrow, bump) = escrow_address(&maker, seed);
+        let (vault, vault_bump) = ata_address(&escrow, &mint_a);
+        let (maker_ata_a, _) = ata_address(&maker, &mint_a);
+
+        let mut escrow_data = [0; Escrow::LEN];
+        Escrow::load_mut(&mut escrow_data).unwrap().set_inner(
+            stored_seed,
+            maker.clone(),
+            mint_a.clone(),
+            Address::new_from_array([3; 32]),
+            1_000,
+            100,
+            0,
+            [bump],
+            [vault_bump],
+            pinocchio_token::ID,
+        );
+
+        vec![
+            TestAccount::empty(maker.clone()).signer().writable(),
+            TestAccount::new(escrow.clone(), escrow_owner, &escrow_data).writable(),
+            TestAccount::new(
+                mint_a.clone(),
+                pinocchio_token::ID,
+                &[0; pinocchio_token::state::Mint::LEN],
+            ),
+            TestAccount::new(
+                vault,
+                pinocchio_token::ID,
+                &token_account_data(&mint_a, &escrow, 100),
+            )
+            .writable(),
+            TestAccount::new(
+                maker_ata_a,
+                pinocchio_token::ID,
+                &token_account_data(&mint_a, &maker, 0),
+            )
+            .writable(),
+            TestAccount::empty(pinocchio_system::ID),
+            TestAccount::empty(pinocchio_token::ID),
+            TestAccount::empty(pinocchio_associated_token_account::ID),
+        ]
+    }
+
+    fn batch_refund(groups: Vec<Vec<TestAccount>>) -> Result<usize, ProgramError> {
+        let mut accounts: Vec<_> = groups.into_iter().flatten().collect();
+        let accounts = views(&mut accounts);
+        BatchRefund::try_from((&[][..], &accounts[..]))
+            .map(|batch| batch.refunds.iter().flatten().count())
+    }
+
+    // 不足一组时报账户数量不足
+    // 整数组之后多出 2 ~ 7 个账户, 或者超过最大组数, 是参数错误
+    #[test]
+    fn account_count() {
+        let mut accounts = empty_accounts(RefundAccounts::LEN * (MAX_BATCH_REFUND_ESCROWS + 1));
+        let accounts = views(&mut accounts);
+
+        for len in [0, 1, RefundAccounts::LEN - 1] {
+            assert_eq!(
+                BatchRefund::try_from((&[][..], &accounts[..len])).err(),
+                Some(ProgramError::NotEnoughAccountKeys)
+            );
+        }
+        for len in [
+            RefundAccounts::LEN + 2,
+            RefundAccounts::LEN * 2 - 1,
+            RefundAccounts::LEN * 2 + 7,
+            RefundAccounts::LEN * (MAX_BATCH_REFUND_ESCROWS + 1),
+        ] {
+            assert_eq!(
+                BatchRefund::try_from((&[][..], &accounts[..len])).err(),
+                Some(ProgramError::InvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_valid_groups() {
+        assert_eq!(batch_refund(vec![group(1, 1, crate::ID)]), Ok(1));
+        assert_eq!(
+            batch_refund(vec![group(1, 1, crate::ID), group(2, 2, crate::ID)]),
+            Ok(2)
+        );
+    }
+
+    // 第二组不合法时整个指令失败, 不会只退款第一组
+    #[test]
+    fn rejects_invalid_second_group() {
+        // escrow 不是本程序拥有的
+        assert_eq!(
+            batch_refund(vec![
+                group(1, 1, crate::ID),
+                group(2, 2, pinocchio_token::ID)
+            ])
+            .err(),
+            Some(EscrowError::InvalidOwner.into())
+        );
+
+        // escrow 中保存的 seed 和地址不匹配
+        assert_eq!(
+            batch_refund(vec![group(1, 1, crate::ID), group(2, 3, crate::ID)]).err(),
+            Some(EscrowError::InvalidEscrowPda.into())
+        );
+    }
+
+    // 同一个 escrow 出现在两组中
+    #[test]
+    fn rejects_duplicate_escrow() {
+        assert_eq!(
+            batch_refund(vec![group(1, 1, crate::ID), group(1, 1, crate::ID)]).err(),
+            Some(EscrowError::InvalidAddress.into())
+        );
+    }
+
+    #[test]
+    fn unpack_accepts_only_empty_data() {
+        assert!(BatchRefundInstructionData::unpack(&[]).is_ok());
+        assert_eq!(
+            BatchRefundInstructionData::unpack(&[0]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+    }
+}
@@ -0,0 +1,176 @@
+// 只读指令, 把 escrow 的数据写入 return data
+// 其他程序可以在 CPI 之后通过 get_return_data 读取, 不需要自己解析账户数据
+use crate::{
+    errors::EscrowError,
+    helpers::{not_enough_account_keys, AccountCheck, InstructionData, ProgramAccount},
+    state::Escrow,
+};
+use pinocchio::{cpi::set_return_data, error::ProgramError, AccountView};
+
+pub struct GetEscrowAccounts<'a> {
+    // 要读取的 escrow 账户, 不需要可写
+    pub escrow: &'a AccountView,
+}
+
+impl<'a> TryFrom<&'a [AccountView]> for GetEscrowAccounts<'a> {
+    type Error = ProgramError;
+
+    fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
+        let [escrow, ..] = accounts else {
+            return Err(not_enough_account_keys("get_escrow", 1, accounts.len()));
+        };
+
+        ProgramAccount::check(escrow)?;
+
+        // owner 和长度之外, 还要确认是真正的 escrow PDA
+        // CPI 调用方会直接信任返回的数据, 其他大小相同的本程序账户不能被当作 escrow 返回
+        {
+            let data = escrow.try_borrow()?;
+            let state = Escrow::load(&data)?;
+            let expected = Escrow::derive_address(&state.maker, state.seed, state.bump[0])?;
+            if escrow.address().ne(&expected) {
+                return Err(EscrowError::InvalidEscrowPda.into());
+            }
+        }
+
+        Ok(Self { escrow })
+    }
+}
+
+// GetEscrow 指令没有额外的数据
+pub struct GetEscrowInstructionData;
+
+impl InstructionData for GetEscrowInstructionData {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if !data.is_empty() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self)
+    }
+}
+
+pub struct GetEscrow<'a> {
+    pub instruction_data: GetEscrowInstructionData,
+    pub accounts: GetEscrowAccounts<'a>,
+}
+
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for GetEscrow<'a> {
+    type Error = ProgramError;
+
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
+        let accounts = GetEscrowAccounts::try_from(accounts)?;
+        let instruction_data = GetEscrowInstructionData::unpack(data)?;
+
+        Ok(Self {
+            instruction_data,
+            accounts,
+        })
+    }
+}
+
+impl<'a> GetEscrow<'a> {
+    pub const DISCRIMINATOR: &'a u8 = &5;
+
+    pub fn process(&self) -> Result<(), ProgramError> {
+        let data = self.accounts.escrow.try_borrow()?;
+        set_return_data(&Self::encode(Escrow::load(&data)?));
+
+        Ok(())
+    }
+
+    // return data 的布局 (固定 Escrow::LEN 字节, 整数都是小端序):
+    // seed [0..8], maker [8..40], mint_a [40..72], mint_b [72..104], receive [104..112], amount [112..120],
+    // deadline [120..128], bump [128], vault_bump [129], token_program [130..162],
+    // has_meta [162] (0 或 1), grace_seconds [163..167] (u32), [167] 保留, 始终为 0
+    // 逐个字段写入, 账户的内存布局以后变化时, 这里的布局保持不变
+    pub fn encode(escrow: &Escrow) -> [u8; Escrow::LEN] {
+        let mut out = [0u8; Escrow::LEN];
+        out[0..8].copy_from_slice(&escrow.seed.to_le_bytes());
+        out[8..40].copy_from_slice(escrow.maker.as_ref());
+        out[40..72].copy_from_slice(escrow.mint_a.as_ref());
+        out[72..104].copy_from_slice(escrow.mint_b.as_ref());
+        out[104..112].copy_from_slice(&escrow.receive.to_le_bytes());
+        out[112..120].copy_from_slice(&escrow.amount.to_le_bytes());
+        out[120..128].copy_from_slice(&escrow.deadline.to_le_bytes());
+        out[128] = escrow.bump[0];
+        out[129] = escrow.vault_bump[0];
+        out[130..162].copy_from_slice(escrow.token_program.as_ref());
+        out[162] = escrow.has_meta() as u8;
+        out[163..167].copy_from_slice(&escrow.grace_seconds().to_le_bytes());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{empty_accounts, views};
+    use pinocchio::Address;
+
+    // 账户不足时报账户数量不足, 多出来的账户不影响解构
+    #[test]
+    fn account_count() {
+        let mut accounts = empty_accounts(1 + 1);
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            GetEscrowAccounts::try_from(&accounts[..1 - 1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_ne!(
+            GetEscrowAccounts::try_from(&accounts[..]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    // 客户端依赖这个布局, 每个字段的偏移量都不能改变
+    #[test]
+    fn encode_layout() {
+        let mut data = [0u8; Escrow::LEN];
+        let escrow = Escrow::load_mut(&mut data).unwrap();
+        escrow.set_inner(
+            0x0102_0304_0506_0708,
+            Address::new_from_array([1; 32]),
+            Address::new_from_array([2; 32]),
+            Address::new_from_array([3; 32]),
+            1_000,
+            500,
+            -2,
+            [254],
+            [253],
+            Address::new_from_array([4; 32]),
+        );
+        escrow.set_has_meta(true);
+        escrow.set_grace_seconds(0xAABB_CCDD);
+
+        let out = GetEscrow::encode(escrow);
+        assert_eq!(out.len(), 168);
+        assert_eq!(out[0..8], 0x0102_0304_0506_0708u64.to_le_bytes());
+        assert_eq!(out[8..40], [1; 32]);
+        assert_eq!(out[40..72], [2; 32]);
+        assert_eq!(out[72..104], [3; 32]);
+        assert_eq!(out[104..112], 1_000u64.to_le_bytes());
+        assert_eq!(out[112..120], 500u64.to_le_bytes());
+        assert_eq!(out[120..128], (-2i64).to_le_bytes());
+        assert_eq!(out[128], 254);
+        assert_eq!(out[129], 253);
+        assert_eq!(out[130..162], [4; 32]);
+        assert_eq!(out[162], 1);
+        assert_eq!(out[163..167], 0xAABB_CCDDu32.to_le_bytes());
+        assert_eq!(out[167], 0);
+
+        // has_meta 为 false 时是 0
+        escrow.set_has_meta(false);
+        assert_eq!(GetEscrow::encode(escrow)[162], 0);
+    }
+
+    #[test]
+    fn unpack_accepts_only_empty_data() {
+        assert!(GetEscrowInstructionData::unpack(&[]).is_ok());
+        assert_eq!(
+            GetEscrowInstructionData::unpack(&[0]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+    }
+}
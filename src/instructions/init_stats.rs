@@ -0,0 +1,129 @@
+// 创建全局统计数据账户
+use crate::{
+    errors::EscrowError,
+    helpers::{
+        not_enough_account_keys, AccountCheck, InstructionData, ProgramAccount, ProgramAccountInit,
+        SignerAccount, WritableAccount,
+    },
+    state::Stats,
+};
+use pinocchio::{cpi::Seed, error::ProgramError, AccountView, Address};
+
+pub struct InitStatsAccounts<'a> {
+    // 支付创建费用的账户
+    pub payer: &'a AccountView,
+    // stats PDA 账户
+    pub stats: &'a AccountView,
+    pub system_program: &'a AccountView,
+}
+
+impl<'a> TryFrom<&'a [AccountView]> for InitStatsAccounts<'a> {
+    type Error = ProgramError;
+
+    fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
+        let [payer, stats, system_program, ..] = accounts else {
+            return Err(not_enough_account_keys("init_stats", 3, accounts.len()));
+        };
+
+        SignerAccount::check(payer)?;
+        WritableAccount::check(payer)?;
+        WritableAccount::check(stats)?;
+
+        Ok(Self {
+            payer,
+            stats,
+            system_program,
+        })
+    }
+}
+
+// InitStats 指令没有额外的数据
+pub struct InitStatsInstructionData;
+
+impl InstructionData for InitStatsInstructionData {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if !data.is_empty() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self)
+    }
+}
+
+pub struct InitStats<'a> {
+    pub instruction_data: InitStatsInstructionData,
+    pub accounts: InitStatsAccounts<'a>,
+    // 缓存的 bump 值
+    pub bump: u8,
+}
+
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for InitStats<'a> {
+    type Error = ProgramError;
+
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
+        let accounts = InitStatsAccounts::try_from(accounts)?;
+        let instruction_data = InitStatsInstructionData::unpack(data)?;
+
+        // stats 是全局唯一的 PDA
+        let (stats_pda, bump) = Address::find_program_address(&[Stats::SEED], &crate::ID);
+        if accounts.stats.address() != &stats_pda {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        let bump_binding = [bump];
+        let stats_seeds = [Seed::from(Stats::SEED), Seed::from(&bump_binding)];
+
+        ProgramAccount::init(accounts.payer, accounts.stats, &stats_seeds, Stats::LEN)?;
+
+        Ok(Self {
+            instruction_data,
+            accounts,
+            bump,
+        })
+    }
+}
+
+impl<'a> InitStats<'a> {
+    pub const DISCRIMINATOR: &'a u8 = &3;
+
+    pub fn process(&self) -> Result<(), ProgramError> {
+        let mut data = self.accounts.stats.try_borrow_mut()?;
+        let stats = Stats::load_mut(data.as_mut())?;
+
+        // 新创建的账户数据都是 0, 计数器不需要初始化
+        stats.set_bump([self.bump]);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{empty_accounts, views};
+
+    // 账户不足时报账户数量不足, 多出来的账户不影响解构
+    #[test]
+    fn account_count() {
+        let mut accounts = empty_accounts(3 + 1);
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            InitStatsAccounts::try_from(&accounts[..3 - 1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_ne!(
+            InitStatsAccounts::try_from(&accounts[..]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn unpack_accepts_only_empty_data() {
+        assert!(InitStatsInstructionData::unpack(&[]).is_ok());
+        assert_eq!(
+            InitStatsInstructionData::unpack(&[0]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+    }
+}
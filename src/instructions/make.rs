@@ -1,13 +1,17 @@
 // 存钱, 创建金库
 use crate::{
+    errors::EscrowError,
     helpers::{
-        AccountCheck, AssociatedTokenAccount, AssociatedTokenAccountCheck,
-        AssociatedTokenAccountInit, MintInterface, ProgramAccount, ProgramAccountInit,
-        SignerAccount,
+        check_distinct, check_transfer_authority, deadline_passed, escrow_signer,
+        is_default_frozen_mint, is_frozen_token_account, load_checked_mut, not_enough_account_keys,
+        read_token_amount, validate_associated_token_program, validate_token_program, AccountCheck,
+        AssociatedTokenAccount, AssociatedTokenAccountCheck, AssociatedTokenAccountInit,
+        DerivedAddresses, InstructionData, MintInterface, ProgramAccount, ProgramAccountInit,
+        SignerAccount, StatsAccount, TokenAccountInterface, UninitializedAccount, WritableAccount,
     },
-    state::Escrow,
+    state::{Escrow, Stats},
 };
-use pinocchio::{cpi::Seed, error::ProgramError, AccountView, Address};
+use pinocchio::{cpi::set_return_data, error::ProgramError, AccountView, Address};
 use pinocchio_token::instructions::Transfer;
 
 // 定义账户列表的结构体
@@ -29,25 +33,74 @@ pub struct MakeAccounts<'a> {
     pub system_program: &'a AccountView,
     // token program
     pub token_program: &'a AccountView,
+    // 可选的 stats 账户, 传入时更新统计数据
+    pub stats: Option<&'a AccountView>,
 }
 
+// Make 指令账户的顺序, 客户端构造指令时按照这个顺序传入账户
+// 和下面 try_from 中的解构顺序保持一致, 修改账户顺序时两处要同时修改
+// 可选的 stats 账户放在最后
+pub const MAKE_ACCOUNT_ORDER: [&str; 9] = [
+    "maker",
+    "escrow",
+    "mint_a",
+    "mint_b",
+    "maker_ata_a",
+    "vault",
+    "system_program",
+    "token_program",
+    "associated_token_program",
+];
+
 // 为账户列表实现 TryFrom trait
 impl<'a> TryFrom<&'a [AccountView]> for MakeAccounts<'a> {
     type Error = ProgramError;
 
     // 校验账户
     fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
-        let [maker, escrow, mint_a, mint_b, maker_ata_a, vault, system_program, token_program, _] =
+        let [maker, escrow, mint_a, mint_b, maker_ata_a, vault, system_program, token_program, associated_token_program, remaining @ ..] =
             accounts
         else {
-            return Err(ProgramError::NotEnoughAccountKeys);
+            return Err(not_enough_account_keys(
+                "make",
+                MAKE_ACCOUNT_ORDER.len(),
+                accounts.len(),
+            ));
         };
 
         // 校验账户
         SignerAccount::check(maker)?;
+        validate_token_program(token_program)?;
+        validate_associated_token_program(associated_token_program)?;
         MintInterface::check(mint_a)?;
         MintInterface::check(mint_b)?;
+        // 用同一种 token 交换自己没有意义, 也会让 taker 误解
+        if mint_a.address() == mint_b.address() {
+            return Err(EscrowError::DuplicateMint.into());
+        }
+        // escrow 只记录一个 token program, 两个 mint 都必须由它管理
+        // mint_a 不匹配时下面的 ATA 校验也会失败, 但 mint_b 在 Make 中没有其他校验,
+        // 混用的话要到 Take 转账 token b 时才在 CPI 中失败
+        if !mint_a.owned_by(token_program.address()) || !mint_b.owned_by(token_program.address()) {
+            return Err(EscrowError::InvalidTokenProgram.into());
+        }
         AssociatedTokenAccount::check(maker_ata_a, maker, mint_a, token_program)?;
+        // escrow 会在 Make 中创建, 必须是空账户
+        // vault 是否需要创建取决于指令数据, 在 Make::try_from 中校验
+        UninitializedAccount::check(escrow)?;
+        // maker 支付租金, 其余账户的数据或余额会被修改
+        WritableAccount::check(maker)?;
+        WritableAccount::check(escrow)?;
+        WritableAccount::check(maker_ata_a)?;
+        WritableAccount::check(vault)?;
+        // 转账的源账户和目标账户, 以及新创建的账户不能重复
+        check_distinct(&[maker, escrow, maker_ata_a, vault])?;
+
+        // 可选的 stats 账户
+        let stats = remaining.first();
+        if let Some(stats) = stats {
+            StatsAccount::check(stats)?;
+        }
 
         Ok(Self {
             maker,
@@ -58,42 +111,108 @@ impl<'a> TryFrom<&'a [AccountView]> for MakeAccounts<'a> {
             vault,
             token_program,
             system_program,
+            stats,
         })
     }
 }
 
 // 定义指令所需的数据结构体
 pub struct MakeInstructionData {
+    // 指令数据的版本, 第一个字节
+    pub version: u8,
     // maker 传入的 seed
     pub seed: u64,
     // 希望接收的 token b 的数量
     pub receive: u64,
     // maker 存入的 token a 的数量
     pub amount: u64,
+    // vault 是否已经由调用方创建 (可选字段, 默认 false)
+    // 通过 CPI 调用 Make 的程序可能会自己预先创建 vault
+    pub existing_vault: bool,
+    // escrow 的过期时间 (可选字段, 默认 0 表示永不过期)
+    pub deadline: i64,
+    // 过期之后 Take 仍然可以成交的宽限时间, 单位秒 (可选字段, 默认 0 表示没有宽限)
+    // 只有设置了 deadline 时才有意义
+    pub grace_seconds: u32,
 }
 
-// 为指令数据实现 TryFrom trait
-impl<'a> TryFrom<&'a [u8]> for MakeInstructionData {
-    type Error = ProgramError;
+impl MakeInstructionData {
+    // 程序能解析的最高版本
+    // 新客户端使用更高的版本 (可能带有新的字段) 时直接拒绝, 而不是截断后解析成错误的字段
+    pub const MAX_VERSION: u8 = 0;
+    // 必须的字段: version + seed + receive + amount
+    pub const LEN: usize = size_of::<u8>() + size_of::<u64>() * 3;
+    // 指令数据的最大长度, 之后新增可选字段时同步增大
+    // 超过这个长度的数据直接拒绝, 不会把多余的字节当作垃圾忽略掉
+    // 可选字段: existing_vault (1 byte), deadline (8 bytes), grace_seconds (4 bytes)
+    // 可选字段按顺序出现, 传后面的字段时必须同时传前面的字段
+    pub const MAX_LEN: usize = Self::LEN + size_of::<u8>() + size_of::<i64>() + size_of::<u32>();
+}
+
+// 为指令数据实现 InstructionData trait
+impl InstructionData for MakeInstructionData {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        // 先检查版本, 更高版本的数据长度可能不同
+        let Some(&version) = data.first() else {
+            return Err(ProgramError::InvalidInstructionData);
+        };
+        if version > Self::MAX_VERSION {
+            return Err(EscrowError::UnsupportedVersion.into());
+        }
 
-    fn try_from(data: &'a [u8]) -> Result<Self, Self::Error> {
-        if data.len() != size_of::<u64>() * 3 {
+        // 可选字段只能完整出现, 不能只传一部分
+        let deadline_end = Self::LEN + size_of::<u8>() + size_of::<i64>();
+        if data.len() != Self::LEN
+            && data.len() != Self::LEN + 1
+            && data.len() != deadline_end
+            && data.len() != Self::MAX_LEN
+        {
             return Err(ProgramError::InvalidInstructionData);
         }
 
-        let seed = u64::from_le_bytes(data[0..8].try_into().unwrap());
-        let receive = u64::from_le_bytes(data[8..16].try_into().unwrap());
-        let amount = u64::from_le_bytes(data[16..24].try_into().unwrap());
+        let seed = u64::from_le_bytes(data[1..9].try_into().unwrap());
+        if Escrow::RESERVED_SEEDS.contains(&seed) {
+            return Err(EscrowError::ReservedSeed.into());
+        }
+        let receive = u64::from_le_bytes(data[9..17].try_into().unwrap());
+        let amount = u64::from_le_bytes(data[17..25].try_into().unwrap());
 
         // 存入的 token a 的数量不能为 0
         if amount == 0 {
             return Err(ProgramError::InvalidInstructionData);
         }
 
+        let existing_vault = match data.get(25) {
+            None | Some(0) => false,
+            Some(1) => true,
+            Some(_) => return Err(ProgramError::InvalidInstructionData),
+        };
+
+        let deadline = match data.get(26..34) {
+            None => 0,
+            Some(bytes) => i64::from_le_bytes(bytes.try_into().unwrap()),
+        };
+        if deadline < 0 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let grace_seconds = match data.get(34..38) {
+            None => 0,
+            Some(bytes) => u32::from_le_bytes(bytes.try_into().unwrap()),
+        };
+        // 永不过期的 escrow 不需要宽限时间, 传了说明客户端搞错了
+        if grace_seconds != 0 && deadline == 0 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
         Ok(Self {
+            version,
             seed,
             receive,
             amount,
+            existing_vault,
+            deadline,
+            grace_seconds,
         })
     }
 }
@@ -103,6 +222,8 @@ pub struct Make<'a> {
     pub accounts: MakeAccounts<'a>,
     // 缓存的 bump 值
     pub bump: u8,
+    // 缓存的 vault bump 值
+    pub vault_bump: u8,
 }
 
 // 为 Make 结构体实现 TryFrom trait
@@ -111,48 +232,36 @@ impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for Make<'a> {
 
     fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
         let accounts = MakeAccounts::try_from(accounts)?;
-        let instruction_data = MakeInstructionData::try_from(data)?;
+        let instruction_data = MakeInstructionData::unpack(data)?;
+
+        let derived = Make::preflight(&accounts, &instruction_data)?;
+        let bump = derived.escrow_bump;
+        let vault_bump = derived.vault_bump;
 
-        // 计算 pda 以及 pda 签名种子
         let seed_binding = instruction_data.seed.to_le_bytes();
-        let (_escrow_pda, bump) = Address::find_program_address(
-            &[
-                b"escrow",
-                &accounts.maker.address().to_bytes(),
-                &seed_binding,
-            ],
-            &crate::ID,
-        );
         let bump_binding = [bump];
-        let escrow_seeds = [
-            Seed::from(b"escrow"),
-            Seed::from(accounts.maker.address().as_ref()),
-            Seed::from(&seed_binding),
-            Seed::from(&bump_binding),
-        ];
+        let escrow_seeds = escrow_signer(accounts.maker.address(), &seed_binding, &bump_binding);
 
         // 创建 escrow PDA 数据账户
-        ProgramAccount::init(
-            &accounts.maker,
-            &accounts.escrow,
-            &escrow_seeds,
-            Escrow::LEN,
-        )?;
-
-        // 创建 vault ATA 账户
-        AssociatedTokenAccount::init(
-            accounts.vault,
-            accounts.mint_a,
-            accounts.maker,
-            accounts.escrow,
-            accounts.system_program,
-            accounts.token_program,
-        )?;
+        ProgramAccount::init(accounts.maker, accounts.escrow, &escrow_seeds, Escrow::LEN)?;
+
+        // 创建 vault ATA 账户, 已经存在的话跳过 ATA 的 CPI
+        if !instruction_data.existing_vault {
+            AssociatedTokenAccount::init(
+                accounts.vault,
+                accounts.mint_a,
+                accounts.maker,
+                accounts.escrow,
+                accounts.system_program,
+                accounts.token_program,
+            )?;
+        }
 
         Ok(Self {
             instruction_data,
             accounts,
             bump,
+            vault_bump,
         })
     }
 }
@@ -161,25 +270,124 @@ impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for Make<'a> {
 impl<'a> Make<'a> {
     pub const DISCRIMINATOR: &'a u8 = &0;
 
+    // Make 创建账户之前的全部校验, 不会修改任何账户
+    // SimulateMake 也使用它, 保证模拟的结果和真正执行 Make 一致
+    // 返回 escrow 和 vault 的地址以及 canonical bump
+    pub fn preflight(
+        accounts: &MakeAccounts<'a>,
+        instruction_data: &MakeInstructionData,
+    ) -> Result<DerivedAddresses, ProgramError> {
+        // 计算 escrow pda 地址和 canonical bump, escrow 必须是这个地址
+        let seed_binding = instruction_data.seed.to_le_bytes();
+        let (escrow, escrow_bump) = Address::find_program_address(
+            &[
+                b"escrow",
+                &accounts.maker.address().to_bytes(),
+                &seed_binding,
+            ],
+            &crate::ID,
+        );
+        if accounts.escrow.address() != &escrow {
+            return Err(EscrowError::InvalidEscrowPda.into());
+        }
+
+        // 计算 vault ATA 的地址和 canonical bump, bump 保存到 escrow 中
+        // 之后 Take 和 Refund 可以直接用 create_program_address 校验 vault
+        let (vault, vault_bump) = Address::find_program_address(
+            &[
+                escrow.as_ref(),
+                accounts.token_program.address().as_ref(),
+                accounts.mint_a.address().as_ref(),
+            ],
+            &pinocchio_associated_token_account::ID,
+        );
+        if accounts.vault.address() != &vault {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        if instruction_data.existing_vault {
+            // vault 由调用方预先创建, 地址已经在上面校验过, 这里只需要校验是 token account
+            // 地址是 (escrow, token_program, mint_a) 的 ATA, 这个地址只有 ATA program 可以签名创建,
+            // 它创建的一定是 owner 为 escrow, mint 为 mint_a 的 token account:
+            // - token account 的 mint 创建之后不能修改
+            // - 修改 owner 需要当前 owner (escrow PDA) 签名, 本程序不会为 SetAuthority 签名
+            //   (token 2022 的 ATA 还带有 ImmutableOwner extension)
+            // 所以不需要再读取 vault 的 owner 和 mint
+            TokenAccountInterface::check(accounts.vault)?;
+
+            // 已经存在的 vault 可以直接检查它自己的冻结状态
+            if is_frozen_token_account(accounts.vault)? {
+                return Err(EscrowError::FrozenVault.into());
+            }
+        } else {
+            UninitializedAccount::check(accounts.vault)?;
+
+            // 如果 mint_a 设置了默认冻结状态, 新创建的 vault 会是冻结的, 无法接收存款
+            // 解冻需要 mint 的 freeze authority 签名, 而程序无法拿到这个签名
+            // 所以这里直接拒绝, 而不是让后面的转账 CPI 报一个含糊的错误
+            if is_default_frozen_mint(accounts.mint_a)? {
+                return Err(EscrowError::FrozenVault.into());
+            }
+        }
+
+        // 已经过期的 escrow 没有意义, 直接拒绝
+        if deadline_passed(instruction_data.deadline)? {
+            return Err(EscrowError::Expired.into());
+        }
+
+        // maker 的 token a 余额必须足够存入
+        if read_token_amount(accounts.maker_ata_a)? < instruction_data.amount {
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        Ok(DerivedAddresses {
+            escrow,
+            escrow_bump,
+            vault,
+            vault_bump,
+        })
+    }
+
+    // Make 和 SimulateMake 的 return data
+    // 布局: escrow 地址 [0..32], bump [32]
+    pub fn return_data(escrow: &Address, bump: u8) -> [u8; size_of::<Address>() + size_of::<u8>()] {
+        let mut return_data = [0u8; size_of::<Address>() + size_of::<u8>()];
+        return_data[..32].copy_from_slice(escrow.as_ref());
+        return_data[32] = bump;
+        return_data
+    }
+
     pub fn process(&self) -> Result<(), ProgramError> {
         // 1. 借用 escrow PDA 链上的数据账户的可变原始内存
-        let mut data = self.accounts.escrow.try_borrow_mut()?;
-
         // 2. 将 escrow 原始内存映射为 Escrow 数据结构体, 只是以 Escrow 结构体的视角去读取这块内存
         // 因为是零拷贝的, 所以 escrow 和 data 此时指向的是同一快内存
-        let escrow = Escrow::load_mut(data.as_mut())?;
+        // load_checked_mut 在借用之前还会检查 owner 和可写
+        load_checked_mut(self.accounts.escrow, |escrow| {
+            // 设置 escrow 数据等同于更改 escrow PDA 的内存, 也就是更改了 escrow PDA 链上的数据
+            escrow.set_inner(
+                self.instruction_data.seed,
+                self.accounts.maker.address().clone(),
+                self.accounts.mint_a.address().clone(),
+                self.accounts.mint_b.address().clone(),
+                self.instruction_data.receive,
+                self.instruction_data.amount,
+                self.instruction_data.deadline,
+                [self.bump],
+                [self.vault_bump],
+                self.accounts.token_program.address().clone(),
+            );
+            escrow.set_grace_seconds(self.instruction_data.grace_seconds);
+        })?;
 
-        // 设置 escrow 数据等同于更改 escrow PDA 的内存, 也就是更改了 escrow PDA 链上的数据
-        escrow.set_inner(
-            self.instruction_data.seed,
-            self.accounts.maker.address().clone(),
-            self.accounts.mint_a.address().clone(),
-            self.accounts.mint_b.address().clone(),
-            self.instruction_data.receive,
-            [self.bump],
-        );
+        // 存款之前再检查一次 vault 的冻结状态
+        // try_from 中只检查了 mint 的默认状态或者已存在 vault 的状态,
+        // 这里确保 vault 在创建之后到存款之前没有被 freeze authority 冻结
+        if is_frozen_token_account(self.accounts.vault)? {
+            return Err(EscrowError::FrozenVault.into());
+        }
 
         // 转账 maker 的 token a 到 vault
+        check_transfer_authority(self.accounts.maker_ata_a, self.accounts.maker)?;
         Transfer {
             from: self.accounts.maker_ata_a, // maker 的 token a 的 ATA 账户
             to: self.accounts.vault,
@@ -188,6 +396,291 @@ impl<'a> Make<'a> {
         }
         .invoke()?;
 
+        if let Some(stats) = self.accounts.stats {
+            let mut data = stats.try_borrow_mut()?;
+            Stats::load_mut(data.as_mut())?.record_make();
+        }
+
+        #[cfg(feature = "indexer-logs")]
+        crate::helpers::log_event(
+            "make",
+            self.instruction_data.seed,
+            self.instruction_data.amount,
+        );
+
+        // 把 escrow 地址和 bump 写入 return data, CPI 调用方和模拟交易可以直接读取
+        set_return_data(&Self::return_data(
+            self.accounts.escrow.address(),
+            self.bump,
+        ));
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        helpers::{TOKEN_2022_PROGRAM_ID, TOKEN_ACCOUNT_STATE_FROZEN, TOKEN_ACCOUNT_STATE_OFFSET},
+        test_utils::{
+            ata_address, empty_accounts, escrow_address, token_account_data, views, TestAccount,
+        },
+    };
+    use pinocchio::Address;
+
+    // MAKE_ACCOUNT_ORDER 的长度必须和 try_from 中解构的账户数量一致
+    // 少一个账户时报账户数量不足, 数量正好时进入后面的账户校验
+    #[test]
+    fn account_order_matches_try_from() {
+        let mut accounts = empty_accounts(MAKE_ACCOUNT_ORDER.len());
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            MakeAccounts::try_from(&accounts[..MAKE_ACCOUNT_ORDER.len() - 1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_eq!(
+            MakeAccounts::try_from(&accounts[..]).err(),
+            Some(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    // version + seed + receive + amount, 后面可以接可选字段
+    fn data(seed: u64, receive: u64, amount: u64, optional: &[u8]) -> [u8; 64] {
+        let mut data = [0u8; 64];
+        data[1..9].copy_from_slice(&seed.to_le_bytes());
+        data[9..17].copy_from_slice(&receive.to_le_bytes());
+        data[17..25].copy_from_slice(&amount.to_le_bytes());
+        data[25..25 + optional.len()].copy_from_slice(optional);
+        data
+    }
+
+    // 通过 MakeAccounts 中 mint 校验之前的所有检查的账户, mint 由调用方提供
+    fn make_accounts(mint_a: TestAccount, mint_b: TestAccount) -> Vec<TestAccount> {
+        let mut accounts = empty_accounts(MAKE_ACCOUNT_ORDER.len());
+        accounts[0] = TestAccount::empty(Address::new_from_array([1; 32])).signer();
+        accounts[2] = mint_a;
+        accounts[3] = mint_b;
+        accounts[7] = TestAccount::empty(pinocchio_token::ID);
+        accounts[8] = TestAccount::empty(pinocchio_associated_token_account::ID);
+        accounts
+    }
+
+    fn mint(address: u8, token_program: Address) -> TestAccount {
+        TestAccount::new(
+            Address::new_from_array([address; 32]),
+            token_program,
+            &[0; pinocchio_token::state::Mint::LEN],
+        )
+    }
+
+    // 通过 MakeAccounts 校验的账户, maker 有 1_000 个 token a
+    // vault 是 escrow 的 mint_a ATA, vault_data 为 None 时是空账户
+    fn preflight(
+        existing_vault: bool,
+        vault_data: Option<Vec<u8>>,
+    ) -> Result<Address, ProgramError> {
+        let maker = Address::new_from_array([1; 32]);
+        let mint_a = Address::new_from_array([10; 32]);
+        let (escrow, _) = escrow_address(&maker, 7);
+        let (vault, _) = ata_address(&escrow, &mint_a);
+        let (maker_ata_a, _) = ata_address(&maker, &mint_a);
+
+        let mut accounts =
+            make_accounts(mint(10, pinocchio_token::ID), mint(11, pinocchio_token::ID));
+        accounts[0] = TestAccount::empty(maker.clone()).signer().writable();
+        accounts[1] = TestAccount::empty(escrow).writable();
+        accounts[4] = TestAccount::new(
+            maker_ata_a,
+            pinocchio_token::ID,
+            &token_account_data(&mint_a, &maker, 1_000),
+        )
+        .writable();
+        accounts[5] = match vault_data {
+            Some(data) => TestAccount::new(vault, pinocchio_token::ID, &data),
+            None => TestAccount::empty(vault),
+        }
+        .writable();
+        accounts[6] = TestAccount::empty(pinocchio_system::ID);
+        let accounts = views(&mut accounts);
+
+        let data = data(7, 100, 50, &[existing_vault as u8]);
+        let ix = MakeInstructionData::unpack(&data[..MakeInstructionData::LEN + 1])?;
+        let accounts = MakeAccounts::try_from(&accounts[..])?;
+        Make::preflight(&accounts, &ix).map(|derived| derived.vault)
+    }
+
+    // 调用方预先创建了 vault
+    #[test]
+    fn preflight_existing_vault() {
+        let maker = Address::new_from_array([1; 32]);
+        let mint_a = Address::new_from_array([10; 32]);
+        let (escrow, _) = escrow_address(&maker, 7);
+        let (vault, _) = ata_address(&escrow, &mint_a);
+        let vault_data = token_account_data(&mint_a, &escrow, 0);
+
+        assert_eq!(preflight(true, Some(vault_data.clone())), Ok(vault));
+        assert!(preflight(false, None).is_ok());
+
+        // 预先创建的 vault 已经被冻结
+        let mut frozen = vault_data.clone();
+        frozen[TOKEN_ACCOUNT_STATE_OFFSET] = TOKEN_ACCOUNT_STATE_FROZEN;
+        assert_eq!(
+            preflight(true, Some(frozen)).err(),
+            Some(EscrowError::FrozenVault.into())
+        );
+
+        // vault 已经存在, 但是没有设置 existing_vault
+        assert_eq!(
+            preflight(false, Some(vault_data)).err(),
+            Some(ProgramError::AccountAlreadyInitialized)
+        );
+
+        // 设置了 existing_vault, 但是 vault 还不存在
+        assert_eq!(
+            preflight(true, None).err(),
+            Some(EscrowError::InvalidOwner.into())
+        );
+    }
+
+    // CPI 调用方依赖这个布局: 33 个字节, escrow 地址在前, bump 在最后
+    #[test]
+    fn return_data_layout() {
+        let (escrow, bump) = escrow_address(&Address::new_from_array([1; 32]), 7);
+        let data = Make::return_data(&escrow, bump);
+
+        assert_eq!(data.len(), 33);
+        assert_eq!(&data[..32], escrow.as_ref());
+        assert_eq!(data[32], bump);
+    }
+
+    // 两个 mint 必须都由传入的 token program 管理
+    #[test]
+    fn rejects_mixed_token_programs() {
+        let mut accounts = make_accounts(
+            mint(10, pinocchio_token::ID),
+            mint(11, TOKEN_2022_PROGRAM_ID),
+        );
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            MakeAccounts::try_from(&accounts[..]).err(),
+            Some(EscrowError::InvalidTokenProgram.into())
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_mint() {
+        let mut accounts =
+            make_accounts(mint(10, pinocchio_token::ID), mint(10, pinocchio_token::ID));
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            MakeAccounts::try_from(&accounts[..]).err(),
+            Some(EscrowError::DuplicateMint.into())
+        );
+    }
+
+    #[test]
+    fn unpack_required_fields() {
+        let data = data(7, 100, 50, &[]);
+        let ix = MakeInstructionData::unpack(&data[..MakeInstructionData::LEN]).unwrap();
+        assert_eq!(ix.version, 0);
+        assert_eq!(ix.seed, 7);
+        assert_eq!(ix.receive, 100);
+        assert_eq!(ix.amount, 50);
+        assert!(!ix.existing_vault);
+        assert_eq!(ix.deadline, 0);
+        assert_eq!(ix.grace_seconds, 0);
+    }
+
+    #[test]
+    fn unpack_optional_fields() {
+        let data = data(7, 100, 50, &[1]);
+        let ix = MakeInstructionData::unpack(&data[..MakeInstructionData::LEN + 1]).unwrap();
+        assert!(ix.existing_vault);
+
+        let mut optional = [0u8; 13];
+        optional[1..9].copy_from_slice(&1_700_000_000i64.to_le_bytes());
+        optional[9..13].copy_from_slice(&600u32.to_le_bytes());
+        let data = self::data(7, 100, 50, &optional);
+        let ix = MakeInstructionData::unpack(&data[..MakeInstructionData::MAX_LEN - 4]).unwrap();
+        assert!(!ix.existing_vault);
+        assert_eq!(ix.deadline, 1_700_000_000);
+        assert_eq!(ix.grace_seconds, 0);
+
+        let ix = MakeInstructionData::unpack(&data[..MakeInstructionData::MAX_LEN]).unwrap();
+        assert_eq!(ix.deadline, 1_700_000_000);
+        assert_eq!(ix.grace_seconds, 600);
+
+        // 没有 deadline 时不能设置宽限时间
+        optional[1..9].copy_from_slice(&0i64.to_le_bytes());
+        let data = self::data(7, 100, 50, &optional);
+        assert_eq!(
+            MakeInstructionData::unpack(&data[..MakeInstructionData::MAX_LEN]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    #[test]
+    fn unpack_rejects_invalid_data() {
+        assert_eq!(
+            MakeInstructionData::unpack(&[]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+
+        // 只传了一部分可选字段, 或者超过最大长度
+        let data = data(7, 100, 50, &[]);
+        for len in [
+            MakeInstructionData::LEN - 1,
+            MakeInstructionData::LEN + 2,
+            MakeInstructionData::MAX_LEN - 1,
+            MakeInstructionData::MAX_LEN + 1,
+        ] {
+            assert_eq!(
+                MakeInstructionData::unpack(&data[..len]).err(),
+                Some(ProgramError::InvalidInstructionData)
+            );
+        }
+
+        // 存入的数量为 0
+        let data = self::data(7, 100, 0, &[]);
+        assert_eq!(
+            MakeInstructionData::unpack(&data[..MakeInstructionData::LEN]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+
+        // existing_vault 不是 0 或 1
+        let data = self::data(7, 100, 50, &[2]);
+        assert_eq!(
+            MakeInstructionData::unpack(&data[..MakeInstructionData::LEN + 1]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+
+        // deadline 为负数
+        let mut optional = [0u8; 9];
+        optional[1..].copy_from_slice(&(-1i64).to_le_bytes());
+        let data = self::data(7, 100, 50, &optional);
+        assert_eq!(
+            MakeInstructionData::unpack(&data[..MakeInstructionData::MAX_LEN]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    #[test]
+    fn unpack_rejects_unsupported_version_and_reserved_seed() {
+        let mut data = data(7, 100, 50, &[]);
+        data[0] = MakeInstructionData::MAX_VERSION + 1;
+        assert_eq!(
+            MakeInstructionData::unpack(&data[..MakeInstructionData::LEN]).err(),
+            Some(EscrowError::UnsupportedVersion.into())
+        );
+
+        let data = self::data(u64::MAX, 100, 50, &[]);
+        assert_eq!(
+            MakeInstructionData::unpack(&data[..MakeInstructionData::LEN]).err(),
+            Some(EscrowError::ReservedSeed.into())
+        );
+    }
+}
@@ -1,16 +1,17 @@
 use crate::{
+    errors::EscrowError,
     helpers::{
-        AccountCheck, AccountClose, AssociatedTokenAccount, AssociatedTokenAccountCheck,
-        AssociatedTokenAccountInit, MintInterface, ProgramAccount, SignerAccount,
+        check_distinct, drain_and_close_vault, drain_vault, escrow_signer, is_frozen_token_account,
+        not_enough_account_keys, read_token_amount, read_token_mint, read_token_owner,
+        validate_associated_token_program, validate_token_program, AccountCheck, AccountClose,
+        AssociatedTokenAccount, AssociatedTokenAccountInit, DerivedAddresses, EscrowMetaAccount,
+        InstructionData, MintInterface, ProgramAccount, SignerAccount, StatsAccount,
+        TokenAccountInterface, WritableAccount,
     },
-    state::Escrow,
+    state::{Escrow, Stats},
 };
-use pinocchio::{
-    cpi::{Seed, Signer},
-    error::ProgramError,
-    AccountView, Address,
-};
-use pinocchio_token::instructions::{CloseAccount, Transfer};
+use pinocchio::{cpi::Signer, error::ProgramError, AccountView};
+use solana_program_log::{log, Logger};
 
 pub struct RefundAccounts<'a> {
     maker: &'a AccountView,
@@ -20,22 +21,67 @@ pub struct RefundAccounts<'a> {
     maker_ata_a: &'a AccountView,
     system_program: &'a AccountView,
     token_program: &'a AccountView,
+    stats: Option<&'a AccountView>,       // 可选的 stats 账户
+    escrow_meta: Option<&'a AccountView>, // 可选的 escrow 元数据账户
+}
+
+// Refund 指令账户的顺序, 和 try_from 中的解构顺序保持一致
+// 可选账户跟在后面: 先是 stats, 然后是 escrow_meta (传 escrow_meta 时必须同时传 stats)
+pub const REFUND_ACCOUNT_ORDER: [&str; 8] = [
+    "maker",
+    "escrow",
+    "mint_a",
+    "vault",
+    "maker_ata_a",
+    "system_program",
+    "token_program",
+    "associated_token_program",
+];
+
+impl RefundAccounts<'_> {
+    // 必须的账户数量 (不包括可选的 stats 和 escrow_meta 账户)
+    pub const LEN: usize = REFUND_ACCOUNT_ORDER.len();
 }
 
 impl<'a> TryFrom<&'a [AccountView]> for RefundAccounts<'a> {
     type Error = ProgramError;
 
     fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
-        let [maker, escrow, mint_a, vault, maker_ata_a, system_program, token_program, _] =
+        let [maker, escrow, mint_a, vault, maker_ata_a, system_program, token_program, associated_token_program, remaining @ ..] =
             accounts
         else {
-            return Err(ProgramError::NotEnoughAccountKeys);
+            return Err(not_enough_account_keys(
+                "refund",
+                RefundAccounts::LEN,
+                accounts.len(),
+            ));
         };
 
         SignerAccount::check(maker)?;
+        validate_token_program(token_program)?;
+        validate_associated_token_program(associated_token_program)?;
         ProgramAccount::check(escrow)?;
         MintInterface::check(mint_a)?;
-        AssociatedTokenAccount::check(vault, escrow, mint_a, token_program)?;
+        // vault 的地址在 Refund::try_from 中通过 DerivedAddresses 校验
+        TokenAccountInterface::check(vault)?;
+        WritableAccount::check(maker)?;
+        WritableAccount::check(escrow)?;
+        WritableAccount::check(vault)?;
+        WritableAccount::check(maker_ata_a)?;
+        // vault 不能同时作为退款的目标账户
+        check_distinct(&[maker, escrow, vault, maker_ata_a])?;
+
+        // 可选的 stats 账户
+        let stats = remaining.first();
+        if let Some(stats) = stats {
+            StatsAccount::check(stats)?;
+        }
+
+        // 可选的 escrow 元数据账户, 跟在 stats 后面, 传入时和 escrow 一起关闭
+        let escrow_meta = remaining.get(1);
+        if let Some(escrow_meta) = escrow_meta {
+            EscrowMetaAccount::check(escrow_meta, escrow)?;
+        }
 
         Ok(Self {
             maker,
@@ -45,97 +91,495 @@ impl<'a> TryFrom<&'a [AccountView]> for RefundAccounts<'a> {
             vault,
             token_program,
             system_program,
+            stats,
+            escrow_meta,
+        })
+    }
+}
+
+pub struct RefundInstructionData {
+    // 是否跳过关闭 vault (可选字段, 默认 false)
+    // token 2022 的某些 extension 会让 vault 无法关闭 (比如 confidential balance 不为 0)
+    // 这时 CloseAccount 会失败, 整个退款都无法执行
+    // 开启后只转出 vault 中的 token 并关闭 escrow, vault 保留
+    // vault 的 owner 是 escrow PDA, escrow 关闭之后没有指令可以直接关闭它 (RepairEscrow 也需要 escrow 账户)
+    // 唯一的恢复方式: 用同一个 seed 和 existing_vault 重新 Make (escrow 地址相同, vault 也就是同一个),
+    // 等 vault 可以关闭之后再正常 Refund, 取回 vault 的租金
+    pub skip_vault_close: bool,
+    // token a 是否退还到 maker 指定的 token account (可选字段, 默认 false)
+    // 开启后 maker_ata_a 可以是 maker 拥有的任意 mint_a token account, 不要求是 ATA, 也不会自动创建
+    pub custom_destination: bool,
+}
+
+impl RefundInstructionData {
+    // 指令数据的最大长度: skip_vault_close (1 byte) + custom_destination (1 byte)
+    pub const MAX_LEN: usize = size_of::<u8>() * 2;
+}
+
+impl InstructionData for RefundInstructionData {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        // 没有数据时使用默认值, 兼容旧的客户端和 BatchRefund
+        if data.len() > Self::MAX_LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let flag = |index: usize| match data.get(index) {
+            None | Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            Some(_) => Err(ProgramError::InvalidInstructionData),
+        };
+
+        Ok(Self {
+            skip_vault_close: flag(0)?,
+            custom_destination: flag(1)?,
         })
     }
 }
 
 pub struct Refund<'a> {
+    pub instruction_data: RefundInstructionData,
     pub accounts: RefundAccounts<'a>,
+    // 缓存的派生地址
+    pub derived: DerivedAddresses,
 }
 
-impl<'a> TryFrom<&'a [AccountView]> for Refund<'a> {
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for Refund<'a> {
     type Error = ProgramError;
 
-    fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
         let accounts = RefundAccounts::try_from(accounts)?;
+        let instruction_data = RefundInstructionData::unpack(data)?;
+
+        // 和 take 一样, 用 escrow 中存储的 seed 和 bump 计算 escrow 和 vault 的地址
+        let derived = {
+            let data = accounts.escrow.try_borrow()?;
+            let escrow = Escrow::load(&data)?;
+
+            // token_program 必须是 Make 时使用的 token program
+            if accounts.token_program.address().ne(&escrow.token_program) {
+                return Err(EscrowError::InvalidTokenProgram.into());
+            }
+
+            // 有元数据账户时必须和 escrow 一起关闭, 否则它的租金再也无法取回
+            if escrow.has_meta() && accounts.escrow_meta.is_none() {
+                return Err(EscrowError::MetadataRequired.into());
+            }
 
-        // 确保 maker_ata_a 账户存在, 没有则创建
-        AssociatedTokenAccount::init_if_needed(
-            accounts.maker_ata_a,
-            accounts.mint_a,
-            accounts.maker,
-            accounts.maker,
-            accounts.system_program,
-            accounts.token_program,
-        )?;
+            DerivedAddresses::derive(
+                accounts.escrow.address(),
+                accounts.maker.address(),
+                escrow.seed,
+                escrow.bump[0],
+                accounts.mint_a.address(),
+                accounts.token_program.address(),
+                escrow.vault_bump[0],
+            )?
+        };
+
+        // 判断 vault 是否是 escrow 的 mint_a ATA
+        if accounts.vault.address() != &derived.vault {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        if instruction_data.custom_destination {
+            // maker 指定的 token account, 必须是 maker 拥有的 mint_a token account
+            TokenAccountInterface::check(accounts.maker_ata_a)?;
+            if read_token_mint(accounts.maker_ata_a)?.ne(accounts.mint_a.address()) {
+                return Err(EscrowError::MintMismatch.into());
+            }
+            if read_token_owner(accounts.maker_ata_a)?.ne(accounts.maker.address()) {
+                return Err(EscrowError::InvalidOwner.into());
+            }
+        } else {
+            // 确保 maker_ata_a 账户存在, 没有则创建
+            AssociatedTokenAccount::init_if_needed(
+                accounts.maker_ata_a,
+                accounts.mint_a,
+                accounts.maker,
+                accounts.maker,
+                accounts.system_program,
+                accounts.token_program,
+            )?;
+        }
+
+        // maker_ata_a 被冻结的话, 退款转账一定会失败
+        // 提前返回明确的错误, 而不是 token program 的通用错误
+        if is_frozen_token_account(accounts.maker_ata_a)? {
+            return Err(EscrowError::FrozenDestination.into());
+        }
 
-        Ok(Self { accounts })
+        Ok(Self {
+            instruction_data,
+            accounts,
+            derived,
+        })
     }
 }
 
 impl<'a> Refund<'a> {
     pub const DISCRIMINATOR: &'a u8 = &2;
 
+    // 用 escrow 中保存的 amount 和 vault 的实际余额做交叉检查
+    // 不一致时 (比如收取转账手续费的 mint, 或者有人直接往 vault 转了 token) 记录下来
+    // 返回 vault 的实际余额, 退款转出全部余额, 不会让 token 留在 vault 中
+    pub fn vault_amount(vault: &AccountView, escrow_amount: u64) -> Result<u64, ProgramError> {
+        let vault_amount = read_token_amount(vault)?;
+        if vault_amount != escrow_amount {
+            let mut logger = Logger::<96>::default();
+            logger
+                .append("Refund: vault balance ")
+                .append(vault_amount)
+                .append(" differs from escrow amount ")
+                .append(escrow_amount);
+            logger.log();
+        }
+
+        Ok(vault_amount)
+    }
+
     pub fn process(&self) -> Result<(), ProgramError> {
+        // escrow 地址已经在 try_from 中校验过了
         // 利用 block 作用域限制借用的生命周期, 离开 block 后, escrow 的借用就会被释放, 避免了手动释放
-        let (seed, bump) = {
+        let (seed, amount) = {
             let data = self.accounts.escrow.try_borrow()?;
             let escrow = Escrow::load(&data)?;
+            (escrow.seed, escrow.amount)
+        };
+
+        let seed_binding = seed.to_le_bytes();
+        let bump = [self.derived.escrow_bump];
+        let escrow_seed = escrow_signer(self.accounts.maker.address(), &seed_binding, &bump);
+        let signers = &[Signer::from(&escrow_seed)];
+
+        // 只有 indexer-logs 的事件日志用到返回的余额
+        #[cfg_attr(not(feature = "indexer-logs"), allow(unused_variables))]
+        let vault_amount = Self::vault_amount(self.accounts.vault, amount)?;
 
-            // 这里使用了 create_program_address, 因为不需要找到 bump
-            let escrow_address = Address::create_program_address(
-                &[
-                    b"escrow",
-                    self.accounts.maker.address().as_ref(),
-                    &escrow.seed.to_le_bytes(),
-                    &escrow.bump,
-                ],
-                &crate::ID,
+        if self.instruction_data.skip_vault_close {
+            // 只从 vault 转账 token 到 maker_ata_a, vault 保留
+            drain_vault(
+                self.accounts.vault,
+                self.accounts.maker_ata_a,
+                self.accounts.escrow,
+                signers,
             )?;
+            log("Refund: vault close skipped; to reclaim its rent, re-run Make with the same seed and existing_vault, then Refund");
+        } else {
+            // 从 vault 转账 token 到 maker_ata_a, 然后关闭 vault token account
+            drain_and_close_vault(
+                self.accounts.vault,
+                self.accounts.maker_ata_a,
+                self.accounts.maker,
+                self.accounts.escrow,
+                signers,
+            )?;
+        }
+
+        #[cfg(feature = "indexer-logs")]
+        crate::helpers::log_event("refund", seed, vault_amount);
+
+        // 关闭 escrow 账户
+        ProgramAccount::close(self.accounts.escrow, self.accounts.maker)?;
+
+        // 关闭元数据账户, 租金退还给 maker
+        if let Some(escrow_meta) = self.accounts.escrow_meta {
+            ProgramAccount::close(escrow_meta, self.accounts.maker)?;
+        }
+
+        if let Some(stats) = self.accounts.stats {
+            let mut data = stats.try_borrow_mut()?;
+            Stats::load_mut(data.as_mut())?.record_refund();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        helpers::{TOKEN_ACCOUNT_STATE_FROZEN, TOKEN_ACCOUNT_STATE_OFFSET},
+        test_utils::{
+            ata_address, empty_accounts, escrow_address, token_account_data, views, TestAccount,
+        },
+    };
+    use pinocchio::Address;
+
+    const SEED: u64 = 42;
+
+    fn maker() -> Address {
+        Address::new_from_array([1; 32])
+    }
+
+    fn mint_a() -> Address {
+        Address::new_from_array([2; 32])
+    }
+
+    // Refund::try_from 的测试场景, 默认是一个合法的 escrow, token a 退还到 maker 已经存在的 ATA
+    struct Case {
+        // vault 是哪个 escrow (同一个 maker 的 seed) 的 mint_a ATA
+        vault_seed: u64,
+        skip_vault_close: bool,
+        custom_destination: bool,
+        // maker_ata_a 的 mint 和 owner, 账户地址是它们的 ATA
+        destination_mint: Address,
+        destination_owner: Address,
+        destination_frozen: bool,
+        // maker_ata_a 的地址, None 时是 destination_owner 的 destination_mint ATA
+        destination_address: Option<Address>,
+    }
 
-            // 判断 escrow 账户是否正确, 和 take 一样
-            if self.accounts.escrow.address() != &escrow_address {
-                return Err(ProgramError::InvalidAccountOwner);
+    impl Default for Case {
+        fn default() -> Self {
+            Self {
+                vault_seed: SEED,
+                skip_vault_close: false,
+                custom_destination: false,
+                destination_mint: mint_a(),
+                destination_owner: maker(),
+                destination_frozen: false,
+                destination_address: None,
             }
+        }
+    }
 
-            (escrow.seed, escrow.bump)
-        };
+    // 执行 Refund::try_from, 不会走到需要 CPI 的分支 (maker_ata_a 总是已经存在)
+    fn refund(case: Case) -> Result<(), ProgramError> {
+        let (maker, mint_a) = (maker(), mint_a());
+        let (escrow, bump) = escrow_address(&maker, SEED);
+        let (_, vault_bump) = ata_address(&escrow, &mint_a);
+        let (vault_owner, _) = escrow_address(&maker, case.vault_seed);
+        let (vault, _) = ata_address(&vault_owner, &mint_a);
 
-        let amount = {
-            let vault_data = self.accounts.vault.try_borrow()?;
-            // pinocchio-token/src/state/token.rs 中 amount 在结构体的第 64 位开始
-            u64::from_le_bytes(vault_data[64..72].try_into().unwrap())
-        };
+        let destination = case
+            .destination_address
+            .unwrap_or_else(|| ata_address(&case.destination_owner, &case.destination_mint).0);
+        let mut destination_data =
+            token_account_data(&case.destination_mint, &case.destination_owner, 0);
+        if case.destination_frozen {
+            destination_data[TOKEN_ACCOUNT_STATE_OFFSET] = TOKEN_ACCOUNT_STATE_FROZEN;
+        }
 
-        let seed_binding = seed.to_le_bytes();
-        let escrow_seed = [
-            Seed::from(b"escrow"),
-            Seed::from(self.accounts.maker.address().as_ref()),
-            Seed::from(&seed_binding),
-            Seed::from(&bump),
+        let mut accounts = vec![
+            TestAccount::empty(maker.clone()).signer().writable(),
+            TestAccount::new(escrow, crate::ID, &[0; Escrow::LEN]).writable(),
+            TestAccount::new(
+                mint_a.clone(),
+                pinocchio_token::ID,
+                &[0; pinocchio_token::state::Mint::LEN],
+            ),
+            TestAccount::new(
+                vault,
+                pinocchio_token::ID,
+                &token_account_data(&mint_a, &vault_owner, 100),
+            )
+            .writable(),
+            TestAccount::new(destination, pinocchio_token::ID, &destination_data).writable(),
+            TestAccount::empty(pinocchio_system::ID),
+            TestAccount::empty(pinocchio_token::ID),
+            TestAccount::empty(pinocchio_associated_token_account::ID),
         ];
-        let signers = &[Signer::from(&escrow_seed)];
+        let accounts = views(&mut accounts);
+
+        Escrow::load_mut(&mut accounts[1].try_borrow_mut()?)?.set_inner(
+            SEED,
+            maker,
+            mint_a,
+            Address::new_from_array([3; 32]),
+            1_000,
+            100,
+            0,
+            [bump],
+            [vault_bump],
+            pinocchio_token::ID,
+        );
+
+        let data = [case.skip_vault_close as u8, case.custom_destination as u8];
+        Refund::try_from((&data[..], &accounts[..])).map(|_| ())
+    }
+
+    #[test]
+    fn accepts_valid_accounts() {
+        assert!(refund(Case::default()).is_ok());
+        assert!(refund(Case {
+            custom_destination: true,
+            ..Case::default()
+        })
+        .is_ok());
+    }
 
-        // 从 vault 转账 token 到 maker_ata_a
-        Transfer {
-            from: self.accounts.vault,
-            to: self.accounts.maker_ata_a,
-            authority: self.accounts.escrow,
-            amount,
+    // skip_vault_close 只影响 process 中是否关闭 vault, 账户校验和正常退款完全一样
+    #[test]
+    fn skip_vault_close_keeps_account_checks() {
+        for custom_destination in [false, true] {
+            assert!(refund(Case {
+                skip_vault_close: true,
+                custom_destination,
+                ..Case::default()
+            })
+            .is_ok());
         }
-        .invoke_signed(signers)?;
 
-        // 关闭 vault token account
-        CloseAccount {
-            account: self.accounts.vault,
-            destination: self.accounts.maker,
-            authority: self.accounts.escrow,
+        assert_eq!(
+            refund(Case {
+                skip_vault_close: true,
+                vault_seed: SEED + 1,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::InvalidAddress.into())
+        );
+        assert_eq!(
+            refund(Case {
+                skip_vault_close: true,
+                destination_frozen: true,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::FrozenDestination.into())
+        );
+    }
+
+    // custom_destination 时 maker_ata_a 可以是 maker 拥有的任意 mint_a token account
+    // strict-ata 模式下 token account 必须是 ATA, 所以不是 ATA 的账户会被拒绝
+    #[test]
+    fn custom_destination_non_ata() {
+        let result = refund(Case {
+            custom_destination: true,
+            destination_address: Some(Address::new_from_array([9; 32])),
+            ..Case::default()
+        });
+
+        #[cfg(not(feature = "strict-ata"))]
+        assert!(result.is_ok());
+        #[cfg(feature = "strict-ata")]
+        assert_eq!(result.err(), Some(EscrowError::InvalidAddress.into()));
+    }
+
+    // custom_destination 时 maker_ata_a 的 mint 和 owner 都要校验
+    // maker_ata_a 是它自己记录的 owner 和 mint 的 ATA, 两种模式下都能通过 token account 校验
+    #[test]
+    fn custom_destination_rejects_wrong_account() {
+        assert_eq!(
+            refund(Case {
+                custom_destination: true,
+                destination_owner: Address::new_from_array([8; 32]),
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::InvalidOwner.into())
+        );
+        assert_eq!(
+            refund(Case {
+                custom_destination: true,
+                destination_mint: Address::new_from_array([3; 32]),
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::MintMismatch.into())
+        );
+    }
+
+    // maker_ata_a 被冻结时提前返回 FrozenDestination, 两种退款目标都一样
+    #[test]
+    fn rejects_frozen_destination() {
+        for custom_destination in [false, true] {
+            assert_eq!(
+                refund(Case {
+                    custom_destination,
+                    destination_frozen: true,
+                    ..Case::default()
+                })
+                .err(),
+                Some(EscrowError::FrozenDestination.into())
+            );
         }
-        .invoke_signed(signers)?;
+    }
 
-        // 关闭 escrow 账户
-        ProgramAccount::close(self.accounts.escrow, self.accounts.maker)?;
+    // vault 是同一个 maker 的另一个 escrow 的 mint_a ATA, 本身是合法的 token account
+    #[test]
+    fn rejects_vault_of_other_escrow() {
+        assert_eq!(
+            refund(Case {
+                vault_seed: SEED + 1,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::InvalidAddress.into())
+        );
+    }
 
-        Ok(())
+    // 退款的数量以 vault 的实际余额为准, 和 escrow.amount 不一致时也转出全部余额
+    #[test]
+    fn vault_amount_cross_check() {
+        let vault = |amount| {
+            TestAccount::new(
+                Address::new_from_array([5; 32]),
+                pinocchio_token::ID,
+                &token_account_data(&mint_a(), &maker(), amount),
+            )
+        };
+
+        // 一致, 收取了转账手续费, 有人直接往 vault 转了 token
+        for (balance, escrow_amount) in [(100, 100), (99, 100), (150, 100)] {
+            assert_eq!(
+                Refund::vault_amount(&vault(balance).view(), escrow_amount),
+                Ok(balance)
+            );
+        }
+
+        // vault 数据不是 token account
+        let mut short = TestAccount::new(
+            Address::new_from_array([5; 32]),
+            pinocchio_token::ID,
+            &[0; 64],
+        );
+        assert_eq!(
+            Refund::vault_amount(&short.view(), 100).err(),
+            Some(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    // REFUND_ACCOUNT_ORDER 的长度必须和 try_from 中解构的账户数量一致
+    // 少一个账户时报账户数量不足, 数量正好时进入后面的账户校验
+    #[test]
+    fn account_order_matches_try_from() {
+        let mut accounts = empty_accounts(REFUND_ACCOUNT_ORDER.len());
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            RefundAccounts::try_from(&accounts[..REFUND_ACCOUNT_ORDER.len() - 1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_eq!(
+            RefundAccounts::try_from(&accounts[..]).err(),
+            Some(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn unpack_flags() {
+        let ix = RefundInstructionData::unpack(&[]).unwrap();
+        assert!(!ix.skip_vault_close);
+        assert!(!ix.custom_destination);
+
+        let ix = RefundInstructionData::unpack(&[1]).unwrap();
+        assert!(ix.skip_vault_close);
+        assert!(!ix.custom_destination);
+
+        let ix = RefundInstructionData::unpack(&[0, 1]).unwrap();
+        assert!(!ix.skip_vault_close);
+        assert!(ix.custom_destination);
+    }
+
+    #[test]
+    fn unpack_rejects_invalid_data() {
+        for data in [&[2][..], &[0, 2], &[0, 0, 0]] {
+            assert_eq!(
+                RefundInstructionData::unpack(data).err(),
+                Some(ProgramError::InvalidInstructionData)
+            );
+        }
     }
 }
@@ -0,0 +1,219 @@
+// 修复不一致的 escrow
+// 正常情况下 Make 是原子的, escrow 和 vault 要么都创建并存入 token, 要么都不存在
+// 这个指令是防御性的工具, 用于处理 escrow 存在但是 vault 缺失或者为空的情况
+//
+// 恢复语义:
+// - vault 不存在: 关闭 escrow, 租金退还给 maker
+// - vault 存在但余额为 0: 关闭 vault 和 escrow, 租金都退还给 maker
+// - vault 有余额: escrow 是一致的, 不需要修复, 返回错误 (应该使用 Refund)
+// 不会重新创建 vault, 没有存款的 escrow 对 taker 没有意义
+// escrow 有元数据账户时, 必须在最后传入它, 和 escrow 一起关闭
+use crate::{
+    errors::EscrowError,
+    helpers::{
+        escrow_signer, not_enough_account_keys, read_token_amount, validate_token_program,
+        AccountCheck, AccountClose, DerivedAddresses, EscrowMetaAccount, InstructionData,
+        MintInterface, ProgramAccount, SignerAccount, TokenAccountInterface, WritableAccount,
+    },
+    state::Escrow,
+};
+use pinocchio::{cpi::Signer, error::ProgramError, AccountView};
+use pinocchio_token::instructions::CloseAccount;
+
+pub struct RepairEscrowAccounts<'a> {
+    pub maker: &'a AccountView,
+    pub escrow: &'a AccountView,
+    pub mint_a: &'a AccountView,
+    pub vault: &'a AccountView,
+    pub token_program: &'a AccountView,
+    // 可选的 escrow 元数据账户
+    pub escrow_meta: Option<&'a AccountView>,
+}
+
+impl<'a> TryFrom<&'a [AccountView]> for RepairEscrowAccounts<'a> {
+    type Error = ProgramError;
+
+    fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
+        let [maker, escrow, mint_a, vault, token_program, remaining @ ..] = accounts else {
+            return Err(not_enough_account_keys("repair_escrow", 5, accounts.len()));
+        };
+
+        SignerAccount::check(maker)?;
+        validate_token_program(token_program)?;
+        ProgramAccount::check(escrow)?;
+        MintInterface::check(mint_a)?;
+        WritableAccount::check(maker)?;
+        WritableAccount::check(escrow)?;
+        WritableAccount::check(vault)?;
+
+        // 可选的 escrow 元数据账户, 传入时和 escrow 一起关闭
+        let escrow_meta = remaining.first();
+        if let Some(escrow_meta) = escrow_meta {
+            EscrowMetaAccount::check(escrow_meta, escrow)?;
+        }
+
+        Ok(Self {
+            maker,
+            escrow,
+            mint_a,
+            vault,
+            token_program,
+            escrow_meta,
+        })
+    }
+}
+
+// RepairEscrow 指令没有额外的数据
+pub struct RepairEscrowInstructionData;
+
+impl InstructionData for RepairEscrowInstructionData {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if !data.is_empty() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self)
+    }
+}
+
+pub struct RepairEscrow<'a> {
+    pub instruction_data: RepairEscrowInstructionData,
+    pub accounts: RepairEscrowAccounts<'a>,
+    // 缓存的派生地址
+    pub derived: DerivedAddresses,
+    // vault 是否存在
+    pub vault_exists: bool,
+}
+
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for RepairEscrow<'a> {
+    type Error = ProgramError;
+
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
+        let accounts = RepairEscrowAccounts::try_from(accounts)?;
+        let instruction_data = RepairEscrowInstructionData::unpack(data)?;
+
+        // 和 Refund 一样, 用 escrow 中存储的数据计算 escrow 和 vault 的地址
+        let derived = {
+            let data = accounts.escrow.try_borrow()?;
+            let escrow = Escrow::load(&data)?;
+
+            // token_program 必须是 Make 时使用的 token program
+            if accounts.token_program.address().ne(&escrow.token_program) {
+                return Err(EscrowError::InvalidTokenProgram.into());
+            }
+
+            // mint_a 必须是 escrow 记录的 mint, 否则会计算出另一个 vault 地址
+            if accounts.mint_a.address() != &escrow.mint_a {
+                return Err(EscrowError::MintMismatch.into());
+            }
+
+            if escrow.has_meta() && accounts.escrow_meta.is_none() {
+                return Err(EscrowError::MetadataRequired.into());
+            }
+
+            DerivedAddresses::derive(
+                accounts.escrow.address(),
+                accounts.maker.address(),
+                escrow.seed,
+                escrow.bump[0],
+                accounts.mint_a.address(),
+                accounts.token_program.address(),
+                escrow.vault_bump[0],
+            )?
+        };
+
+        if accounts.vault.address() != &derived.vault {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        // vault 不存在时是一个空的 system 账户
+        let vault_exists = !accounts.vault.is_data_empty();
+        if vault_exists {
+            TokenAccountInterface::check(accounts.vault)?;
+
+            // vault 中还有 token, escrow 是完整的, 不需要修复
+            if read_token_amount(accounts.vault)? != 0 {
+                return Err(ProgramError::InvalidArgument);
+            }
+        }
+
+        Ok(Self {
+            instruction_data,
+            accounts,
+            derived,
+            vault_exists,
+        })
+    }
+}
+
+impl<'a> RepairEscrow<'a> {
+    pub const DISCRIMINATOR: &'a u8 = &6;
+
+    pub fn process(&self) -> Result<(), ProgramError> {
+        let seed = {
+            let data = self.accounts.escrow.try_borrow()?;
+            Escrow::load(&data)?.seed
+        };
+
+        if self.vault_exists {
+            let seed_binding = seed.to_le_bytes();
+            let bump_binding = [self.derived.escrow_bump];
+            let escrow_seed =
+                escrow_signer(self.accounts.maker.address(), &seed_binding, &bump_binding);
+            let signers = &[Signer::from(&escrow_seed)];
+
+            // 空的 vault 直接关闭, 租金退还给 maker
+            CloseAccount {
+                account: self.accounts.vault,
+                destination: self.accounts.maker,
+                authority: self.accounts.escrow,
+            }
+            .invoke_signed(signers)?;
+        }
+
+        // 修复时 vault 中没有 token, 转移的数量总是 0
+        #[cfg(feature = "indexer-logs")]
+        crate::helpers::log_event("repair", seed, 0);
+
+        // 关闭 escrow 账户
+        ProgramAccount::close(self.accounts.escrow, self.accounts.maker)?;
+
+        // 关闭元数据账户, 租金退还给 maker
+        if let Some(escrow_meta) = self.accounts.escrow_meta {
+            ProgramAccount::close(escrow_meta, self.accounts.maker)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{empty_accounts, views};
+
+    // 账户不足时报账户数量不足, 多出来的账户不影响解构
+    #[test]
+    fn account_count() {
+        let mut accounts = empty_accounts(5 + 1);
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            RepairEscrowAccounts::try_from(&accounts[..5 - 1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_ne!(
+            RepairEscrowAccounts::try_from(&accounts[..]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn unpack_accepts_only_empty_data() {
+        assert!(RepairEscrowInstructionData::unpack(&[]).is_ok());
+        assert_eq!(
+            RepairEscrowInstructionData::unpack(&[0]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+    }
+}
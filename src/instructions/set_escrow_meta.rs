@@ -0,0 +1,185 @@
+// 为 escrow 创建元数据账户, 保存一个变长的 URI
+// 元数据账户会在 Take, Refund 或 RepairEscrow 关闭 escrow 时一起关闭, 租金退还给 maker
+// escrow 中的 has_meta 标记之后, 这些指令不传入元数据账户会失败, 元数据账户不会被遗留
+use crate::{
+    errors::EscrowError,
+    helpers::{
+        load_checked_mut, not_enough_account_keys, AccountCheck, ProgramAccount,
+        ProgramAccountInit, SignerAccount, UninitializedAccount, WritableAccount,
+    },
+    state::{Escrow, EscrowMeta, Stats},
+};
+use pinocchio::{cpi::Seed, error::ProgramError, AccountView, Address};
+
+pub struct SetEscrowMetaAccounts<'a> {
+    // escrow 的创建者, 支付元数据账户的租金
+    pub maker: &'a AccountView,
+    pub escrow: &'a AccountView,
+    // 元数据 PDA 账户
+    pub escrow_meta: &'a AccountView,
+    pub system_program: &'a AccountView,
+}
+
+impl<'a> TryFrom<&'a [AccountView]> for SetEscrowMetaAccounts<'a> {
+    type Error = ProgramError;
+
+    fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
+        let [maker, escrow, escrow_meta, system_program, ..] = accounts else {
+            return Err(not_enough_account_keys(
+                "set_escrow_meta",
+                4,
+                accounts.len(),
+            ));
+        };
+
+        SignerAccount::check(maker)?;
+        ProgramAccount::check(escrow)?;
+        UninitializedAccount::check(escrow_meta)?;
+        WritableAccount::check(maker)?;
+        // escrow 中要记录 has_meta
+        WritableAccount::check(escrow)?;
+        WritableAccount::check(escrow_meta)?;
+
+        // 只有 escrow 的 maker 可以设置元数据
+        {
+            let data = escrow.try_borrow()?;
+            if Escrow::load(&data)?.maker.ne(maker.address()) {
+                return Err(EscrowError::InvalidAddress.into());
+            }
+        }
+
+        Ok(Self {
+            maker,
+            escrow,
+            escrow_meta,
+            system_program,
+        })
+    }
+}
+
+pub struct SetEscrowMetaInstructionData<'a> {
+    // UTF-8 编码的 URI
+    pub uri: &'a [u8],
+}
+
+impl<'a> SetEscrowMetaInstructionData<'a> {
+    // InstructionData::unpack 返回的是拥有所有权的数据, 这里需要借用指令数据, 所以单独实现
+    pub fn unpack(data: &'a [u8]) -> Result<Self, ProgramError> {
+        if data.is_empty() || data.len() > EscrowMeta::MAX_URI_LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        if core::str::from_utf8(data).is_err() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        // 元数据账户也由本程序拥有, 账户之间只靠 owner 和长度区分类型
+        // 大小和 Escrow 或 Stats 相同的元数据账户可能被当作它们传入, 所以拒绝这两个长度
+        let len = EscrowMeta::HEADER_LEN + data.len();
+        if len == Escrow::LEN || len == Stats::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self { uri: data })
+    }
+}
+
+pub struct SetEscrowMeta<'a> {
+    pub instruction_data: SetEscrowMetaInstructionData<'a>,
+    pub accounts: SetEscrowMetaAccounts<'a>,
+    // 缓存的 bump 值
+    pub bump: u8,
+}
+
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for SetEscrowMeta<'a> {
+    type Error = ProgramError;
+
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
+        let accounts = SetEscrowMetaAccounts::try_from(accounts)?;
+        let instruction_data = SetEscrowMetaInstructionData::unpack(data)?;
+
+        let (meta_pda, bump) = Address::find_program_address(
+            &[EscrowMeta::SEED, accounts.escrow.address().as_ref()],
+            &crate::ID,
+        );
+        if accounts.escrow_meta.address() != &meta_pda {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        let bump_binding = [bump];
+        let meta_seeds = [
+            Seed::from(EscrowMeta::SEED),
+            Seed::from(accounts.escrow.address().as_ref()),
+            Seed::from(&bump_binding),
+        ];
+
+        // 账户大小由 uri 的长度决定
+        ProgramAccount::init(
+            accounts.maker,
+            accounts.escrow_meta,
+            &meta_seeds,
+            EscrowMeta::HEADER_LEN + instruction_data.uri.len(),
+        )?;
+
+        Ok(Self {
+            instruction_data,
+            accounts,
+            bump,
+        })
+    }
+}
+
+impl<'a> SetEscrowMeta<'a> {
+    pub const DISCRIMINATOR: &'a u8 = &7;
+
+    pub fn process(&self) -> Result<(), ProgramError> {
+        let mut data = self.accounts.escrow_meta.try_borrow_mut()?;
+
+        data[0] = self.bump;
+        data[EscrowMeta::HEADER_LEN..].copy_from_slice(self.instruction_data.uri);
+
+        load_checked_mut(self.accounts.escrow, |escrow| escrow.set_has_meta(true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{empty_accounts, views};
+
+    // 账户不足时报账户数量不足, 多出来的账户不影响解构
+    #[test]
+    fn account_count() {
+        let mut accounts = empty_accounts(4 + 1);
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            SetEscrowMetaAccounts::try_from(&accounts[..4 - 1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_ne!(
+            SetEscrowMetaAccounts::try_from(&accounts[..]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn unpack_uri() {
+        let ix = SetEscrowMetaInstructionData::unpack(b"https://example.com/escrow.json").unwrap();
+        assert_eq!(ix.uri, b"https://example.com/escrow.json");
+    }
+
+    #[test]
+    fn unpack_rejects_invalid_uri() {
+        let too_long = [b'a'; EscrowMeta::MAX_URI_LEN + 1];
+        // 账户大小会和 Escrow 或 Stats 相同
+        let escrow_len = [b'a'; Escrow::LEN - EscrowMeta::HEADER_LEN];
+        let stats_len = [b'a'; Stats::LEN - EscrowMeta::HEADER_LEN];
+        for data in [&[][..], &too_long, &[0xff, 0xfe], &escrow_len, &stats_len] {
+            assert_eq!(
+                SetEscrowMetaInstructionData::unpack(data).err(),
+                Some(ProgramError::InvalidInstructionData)
+            );
+        }
+    }
+}
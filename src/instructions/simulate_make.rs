@@ -0,0 +1,46 @@
+// 模拟 Make, 只做校验, 不创建账户也不转账
+// 客户端可以在真正发送 Make 之前确认它能否成功 (余额, mint, escrow 地址冲突等)
+// 账户和指令数据都和 Make 完全一样, 失败时返回和 Make 相同的错误
+use crate::{
+    helpers::{DerivedAddresses, InstructionData},
+    instructions::{Make, MakeAccounts, MakeInstructionData},
+};
+use pinocchio::{cpi::set_return_data, error::ProgramError, AccountView};
+
+pub struct SimulateMake<'a> {
+    pub instruction_data: MakeInstructionData,
+    pub accounts: MakeAccounts<'a>,
+    // Make 会使用的派生地址
+    pub derived: DerivedAddresses,
+}
+
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for SimulateMake<'a> {
+    type Error = ProgramError;
+
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
+        let accounts = MakeAccounts::try_from(accounts)?;
+        let instruction_data = MakeInstructionData::unpack(data)?;
+        let derived = Make::preflight(&accounts, &instruction_data)?;
+
+        Ok(Self {
+            instruction_data,
+            accounts,
+            derived,
+        })
+    }
+}
+
+impl<'a> SimulateMake<'a> {
+    pub const DISCRIMINATOR: &'a u8 = &8;
+
+    // 校验全部通过时, 把 Make 会创建的 escrow 地址和 bump 写入 return data
+    // 布局和 Make 的 return data 一样: escrow 地址 [0..32], bump [32]
+    pub fn process(&self) -> Result<(), ProgramError> {
+        set_return_data(&Make::return_data(
+            &self.derived.escrow,
+            self.derived.escrow_bump,
+        ));
+
+        Ok(())
+    }
+}
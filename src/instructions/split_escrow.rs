@@ -0,0 +1,444 @@
+// 把一个 escrow 拆分成两个, maker 签名
+// 新的 escrow 使用新的 seed, 从原来的 vault 转入一部分 token a,
+// 并按照同样的比例分走 receive, 原来的 escrow 保留剩下的部分
+// 两个 escrow 之后各自独立地被 Take 或 Refund
+// 元数据账户属于原来的 escrow, 新的 escrow 没有元数据 (需要的话之后再调用 SetEscrowMeta)
+use crate::{
+    errors::EscrowError,
+    helpers::{
+        check_distinct, check_transfer_authority, escrow_signer, load_checked_mut,
+        not_enough_account_keys, validate_associated_token_program, validate_token_program,
+        AccountCheck, AssociatedTokenAccount, AssociatedTokenAccountInit, DerivedAddresses,
+        InstructionData, MintInterface, ProgramAccount, ProgramAccountInit, SignerAccount,
+        TokenAccountInterface, UninitializedAccount, WritableAccount,
+    },
+    state::Escrow,
+};
+use pinocchio::{cpi::Signer, error::ProgramError, AccountView, Address};
+use pinocchio_token::instructions::Transfer;
+
+pub struct SplitEscrowAccounts<'a> {
+    pub maker: &'a AccountView,
+    // 原来的 escrow
+    pub escrow: &'a AccountView,
+    // 拆分出来的新 escrow
+    pub new_escrow: &'a AccountView,
+    pub mint_a: &'a AccountView,
+    // 原来的 vault
+    pub vault: &'a AccountView,
+    // 新 escrow 的 vault
+    pub new_vault: &'a AccountView,
+    pub system_program: &'a AccountView,
+    pub token_program: &'a AccountView,
+}
+
+impl<'a> TryFrom<&'a [AccountView]> for SplitEscrowAccounts<'a> {
+    type Error = ProgramError;
+
+    fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
+        let [maker, escrow, new_escrow, mint_a, vault, new_vault, system_program, token_program, associated_token_program, ..] =
+            accounts
+        else {
+            return Err(not_enough_account_keys("split_escrow", 9, accounts.len()));
+        };
+
+        SignerAccount::check(maker)?;
+        validate_token_program(token_program)?;
+        validate_associated_token_program(associated_token_program)?;
+        ProgramAccount::check(escrow)?;
+        MintInterface::check(mint_a)?;
+        TokenAccountInterface::check(vault)?;
+        // 新的 escrow 和 vault 都会在这里创建
+        UninitializedAccount::check(new_escrow)?;
+        UninitializedAccount::check(new_vault)?;
+        WritableAccount::check(maker)?;
+        WritableAccount::check(escrow)?;
+        WritableAccount::check(new_escrow)?;
+        WritableAccount::check(vault)?;
+        WritableAccount::check(new_vault)?;
+        check_distinct(&[maker, escrow, new_escrow, vault, new_vault])?;
+
+        Ok(Self {
+            maker,
+            escrow,
+            new_escrow,
+            mint_a,
+            vault,
+            new_vault,
+            system_program,
+            token_program,
+        })
+    }
+}
+
+pub struct SplitEscrowInstructionData {
+    // 新 escrow 的 seed
+    pub new_seed: u64,
+    // 转入新 escrow 的 token a 数量
+    pub amount: u64,
+}
+
+impl SplitEscrowInstructionData {
+    // new_seed (8 bytes) + amount (8 bytes)
+    pub const LEN: usize = size_of::<u64>() * 2;
+}
+
+impl InstructionData for SplitEscrowInstructionData {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() != Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let new_seed = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        if Escrow::RESERVED_SEEDS.contains(&new_seed) {
+            return Err(EscrowError::ReservedSeed.into());
+        }
+
+        let amount = u64::from_le_bytes(data[8..16].try_into().unwrap());
+        if amount == 0 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self { new_seed, amount })
+    }
+}
+
+// 拆分之后两个 escrow 的 receive 和 amount
+#[derive(Debug, PartialEq)]
+pub struct SplitTerms {
+    // 新 escrow 的 receive
+    pub new_receive: u64,
+    // 原来 escrow 剩下的 receive
+    pub remaining_receive: u64,
+    // 原来 escrow 剩下的存款数量
+    pub remaining_amount: u64,
+}
+
+// 计算拆分: 转出 split_amount 个 token a, receive 按照 split_amount / amount 的比例分给新 escrow
+// - amount 是 escrow 中记录的存款数量, 和 Take 一样以它为准, 不使用 vault 的余额
+// - split_amount 必须小于 amount, 两个 escrow 都要有存款
+// - 使用 u128 避免溢出, 向下取整, 多出来的部分留在原来的 escrow 中
+pub fn split_terms(
+    receive: u64,
+    amount: u64,
+    split_amount: u64,
+) -> Result<SplitTerms, ProgramError> {
+    if split_amount >= amount {
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    let new_receive = (receive as u128 * split_amount as u128 / amount as u128) as u64;
+    // 拆分的数量太小时 new_receive 向下取整为 0, 这样的 escrow 无法通过 Take 的结算检查, 只能退款
+    if new_receive < Escrow::MIN_SETTLEMENT_AMOUNT {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    Ok(SplitTerms {
+        new_receive,
+        remaining_receive: receive
+            .checked_sub(new_receive)
+            .ok_or(ProgramError::ArithmeticOverflow)?,
+        remaining_amount: amount
+            .checked_sub(split_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?,
+    })
+}
+
+pub struct SplitEscrow<'a> {
+    pub instruction_data: SplitEscrowInstructionData,
+    pub accounts: SplitEscrowAccounts<'a>,
+    // 原来 escrow 的派生地址
+    pub derived: DerivedAddresses,
+    // 新 escrow 的派生地址
+    pub new_derived: DerivedAddresses,
+    // 原来 escrow 的 seed
+    pub seed: u64,
+    // 新 escrow 的 receive
+    pub new_receive: u64,
+    // 原来 escrow 剩下的 receive
+    pub remaining_receive: u64,
+    // 原来 escrow 剩下的存款数量
+    pub remaining_amount: u64,
+    // 两个 escrow 共同的 mint_b
+    pub mint_b: Address,
+    // 新 escrow 继承原来的过期时间和宽限时间
+    pub deadline: i64,
+    pub grace_seconds: u32,
+}
+
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for SplitEscrow<'a> {
+    type Error = ProgramError;
+
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
+        let accounts = SplitEscrowAccounts::try_from(accounts)?;
+        let instruction_data = SplitEscrowInstructionData::unpack(data)?;
+
+        let (derived, seed, receive, amount, deadline, grace_seconds, mint_b) = {
+            let data = accounts.escrow.try_borrow()?;
+            let escrow = Escrow::load(&data)?;
+
+            if accounts.token_program.address().ne(&escrow.token_program) {
+                return Err(EscrowError::InvalidTokenProgram.into());
+            }
+
+            if accounts.mint_a.address().ne(&escrow.mint_a) {
+                return Err(EscrowError::MintMismatch.into());
+            }
+
+            let derived = DerivedAddresses::derive(
+                accounts.escrow.address(),
+                accounts.maker.address(),
+                escrow.seed,
+                escrow.bump[0],
+                accounts.mint_a.address(),
+                accounts.token_program.address(),
+                escrow.vault_bump[0],
+            )?;
+
+            (
+                derived,
+                escrow.seed,
+                escrow.receive,
+                escrow.amount,
+                escrow.deadline,
+                escrow.grace_seconds(),
+                escrow.mint_b.clone(),
+            )
+        };
+
+        if accounts.vault.address() != &derived.vault {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        let SplitTerms {
+            new_receive,
+            remaining_receive,
+            remaining_amount,
+        } = split_terms(receive, amount, instruction_data.amount)?;
+
+        // 新 escrow 和新 vault 的地址, 和 Make 的派生方式一样
+        let new_seed_binding = instruction_data.new_seed.to_le_bytes();
+        let (new_escrow, new_escrow_bump) = Address::find_program_address(
+            &[
+                Escrow::SEED,
+                accounts.maker.address().as_ref(),
+                &new_seed_binding,
+            ],
+            &crate::ID,
+        );
+        if accounts.new_escrow.address() != &new_escrow {
+            return Err(EscrowError::InvalidEscrowPda.into());
+        }
+
+        let (new_vault, new_vault_bump) = Address::find_program_address(
+            &[
+                new_escrow.as_ref(),
+                accounts.token_program.address().as_ref(),
+                accounts.mint_a.address().as_ref(),
+            ],
+            &pinocchio_associated_token_account::ID,
+        );
+        if accounts.new_vault.address() != &new_vault {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        // 创建新的 escrow 和 vault, maker 支付租金
+        let bump_binding = [new_escrow_bump];
+        let new_escrow_seeds =
+            escrow_signer(accounts.maker.address(), &new_seed_binding, &bump_binding);
+        ProgramAccount::init(
+            accounts.maker,
+            accounts.new_escrow,
+            &new_escrow_seeds,
+            Escrow::LEN,
+        )?;
+
+        AssociatedTokenAccount::init(
+            accounts.new_vault,
+            accounts.mint_a,
+            accounts.maker,
+            accounts.new_escrow,
+            accounts.system_program,
+            accounts.token_program,
+        )?;
+
+        Ok(Self {
+            instruction_data,
+            accounts,
+            derived,
+            new_derived: DerivedAddresses {
+                escrow: new_escrow,
+                escrow_bump: new_escrow_bump,
+                vault: new_vault,
+                vault_bump: new_vault_bump,
+            },
+            seed,
+            new_receive,
+            remaining_receive,
+            remaining_amount,
+            mint_b,
+            deadline,
+            grace_seconds,
+        })
+    }
+}
+
+impl<'a> SplitEscrow<'a> {
+    pub const DISCRIMINATOR: &'a u8 = &9;
+
+    pub fn process(&self) -> Result<(), ProgramError> {
+        // 写入新的 escrow, 除了 seed, receive, amount 和 bump 以外都和原来的一样
+        load_checked_mut(self.accounts.new_escrow, |escrow| {
+            escrow.set_inner(
+                self.instruction_data.new_seed,
+                self.accounts.maker.address().clone(),
+                self.accounts.mint_a.address().clone(),
+                self.mint_b.clone(),
+                self.new_receive,
+                self.instruction_data.amount,
+                self.deadline,
+                [self.new_derived.escrow_bump],
+                [self.new_derived.vault_bump],
+                self.accounts.token_program.address().clone(),
+            );
+            escrow.set_grace_seconds(self.grace_seconds);
+        })?;
+
+        // 原来的 escrow 保留剩下的 receive
+        load_checked_mut(self.accounts.escrow, |escrow| {
+            escrow.set_receive(self.remaining_receive);
+            escrow.set_amount(self.remaining_amount);
+        })?;
+
+        // 从原来的 vault 转账到新的 vault, 由原来的 escrow 签名
+        let seed_binding = self.seed.to_le_bytes();
+        let bump_binding = [self.derived.escrow_bump];
+        let escrow_seed =
+            escrow_signer(self.accounts.maker.address(), &seed_binding, &bump_binding);
+        let signers = &[Signer::from(&escrow_seed)];
+
+        check_transfer_authority(self.accounts.vault, self.accounts.escrow)?;
+        Transfer {
+            from: self.accounts.vault,
+            to: self.accounts.new_vault,
+            authority: self.accounts.escrow,
+            amount: self.instruction_data.amount,
+        }
+        .invoke_signed(signers)?;
+
+        // 原来的 escrow 转出了 amount, 新的 escrow 存入了 amount
+        #[cfg(feature = "indexer-logs")]
+        {
+            crate::helpers::log_event("split", self.seed, self.instruction_data.amount);
+            crate::helpers::log_event(
+                "split_new",
+                self.instruction_data.new_seed,
+                self.instruction_data.amount,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{empty_accounts, views};
+
+    // 账户不足时报账户数量不足, 多出来的账户不影响解构
+    #[test]
+    fn account_count() {
+        let mut accounts = empty_accounts(9 + 1);
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            SplitEscrowAccounts::try_from(&accounts[..9 - 1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_ne!(
+            SplitEscrowAccounts::try_from(&accounts[..]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn split_proportionally() {
+        assert_eq!(
+            split_terms(1_000, 100, 40),
+            Ok(SplitTerms {
+                new_receive: 400,
+                remaining_receive: 600,
+                remaining_amount: 60,
+            })
+        );
+
+        // 向下取整, 多出来的留在原来的 escrow: 10 * 1 / 3 = 3.3
+        assert_eq!(
+            split_terms(10, 3, 1),
+            Ok(SplitTerms {
+                new_receive: 3,
+                remaining_receive: 7,
+                remaining_amount: 2,
+            })
+        );
+
+        // 乘法不会溢出
+        assert_eq!(
+            split_terms(u64::MAX, u64::MAX, u64::MAX - 1),
+            Ok(SplitTerms {
+                new_receive: u64::MAX - 1,
+                remaining_receive: 1,
+                remaining_amount: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn split_rejects_invalid_amounts() {
+        // 拆分的数量必须小于存款
+        for split_amount in [100, 101] {
+            assert_eq!(
+                split_terms(1_000, 100, split_amount),
+                Err(ProgramError::InsufficientFunds)
+            );
+        }
+
+        // 新 escrow 的 receive 向下取整为 0: 5 * 1 / 10 = 0.5
+        assert_eq!(split_terms(5, 10, 1), Err(ProgramError::InvalidArgument));
+    }
+
+    fn data(new_seed: u64, amount: u64) -> [u8; SplitEscrowInstructionData::LEN] {
+        let mut data = [0u8; SplitEscrowInstructionData::LEN];
+        data[0..8].copy_from_slice(&new_seed.to_le_bytes());
+        data[8..16].copy_from_slice(&amount.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn unpack_fields() {
+        let ix = SplitEscrowInstructionData::unpack(&data(3, 40)).unwrap();
+        assert_eq!(ix.new_seed, 3);
+        assert_eq!(ix.amount, 40);
+    }
+
+    #[test]
+    fn unpack_rejects_invalid_data() {
+        assert_eq!(
+            SplitEscrowInstructionData::unpack(&[]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(
+            SplitEscrowInstructionData::unpack(&data(3, 40)[..15]).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(
+            SplitEscrowInstructionData::unpack(&data(3, 0)).err(),
+            Some(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(
+            SplitEscrowInstructionData::unpack(&data(u64::MAX, 40)).err(),
+            Some(EscrowError::ReservedSeed.into())
+        );
+    }
+}
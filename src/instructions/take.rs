@@ -1,16 +1,19 @@
 use crate::{
+    errors::EscrowError,
     helpers::{
+        check_distinct, check_transfer_authority, deadline_passed_at, drain_and_close_vault,
+        escrow_signer, load_checked_mut, not_enough_account_keys, read_token_amount,
+        read_token_mint, unix_timestamp, validate_associated_token_program, validate_token_program,
         AccountCheck, AccountClose, AssociatedTokenAccount, AssociatedTokenAccountCheck,
-        AssociatedTokenAccountInit, MintInterface, ProgramAccount, SignerAccount,
+        AssociatedTokenAccountInit, DerivedAddresses, EscrowMetaAccount, InstructionData,
+        MintInterface, ProgramAccount, SignerAccount, StatsAccount, TokenAccountInterface,
+        WritableAccount, NATIVE_MINT,
     },
-    state::Escrow,
+    state::{Escrow, Stats},
 };
-use pinocchio::{
-    cpi::{Seed, Signer},
-    error::ProgramError,
-    AccountView, Address,
-};
-use pinocchio_token::instructions::{CloseAccount, Transfer};
+use pinocchio::{cpi::Signer, error::ProgramError, AccountView};
+use pinocchio_token::instructions::{SyncNative, Transfer};
+use solana_program_log::log;
 
 pub struct TakeAccounts<'a> {
     taker: &'a AccountView,
@@ -18,30 +21,94 @@ pub struct TakeAccounts<'a> {
     escrow: &'a AccountView,
     mint_a: &'a AccountView,
     mint_b: &'a AccountView,
-    vault: &'a AccountView,       // vault 账户
-    taker_ata_a: &'a AccountView, // 从 vault 转账到 taker 的 token a 的 ata 账户
+    vault: &'a AccountView,            // vault 账户
+    taker_ata_a: &'a AccountView, // funds destination: 从 vault 转账到 taker 的 token a 的 ata 账户
     taker_ata_b: &'a AccountView, // 账户给 maker 的 token b 的 ata 账户转账
     maker_ata_b: &'a AccountView, // 从 taker 账户转账到 maker 的 token b 的 ata 账户
+    rent_destination: &'a AccountView, // 关闭 escrow 后接收租金的账户
     system_program: &'a AccountView,
     token_program: &'a AccountView,
+    stats: Option<&'a AccountView>,       // 可选的 stats 账户
+    escrow_meta: Option<&'a AccountView>, // 可选的 escrow 元数据账户
 }
 
+// Take 指令账户的顺序, 和 try_from 中的解构顺序保持一致
+// 可选账户跟在后面: 先是 stats, 然后是 escrow_meta (传 escrow_meta 时必须同时传 stats)
+pub const TAKE_ACCOUNT_ORDER: [&str; 13] = [
+    "taker",
+    "maker",
+    "escrow",
+    "mint_a",
+    "mint_b",
+    "vault",
+    "taker_ata_a",
+    "taker_ata_b",
+    "maker_ata_b",
+    "rent_destination",
+    "system_program",
+    "token_program",
+    "associated_token_program",
+];
+
 impl<'a> TryFrom<&'a [AccountView]> for TakeAccounts<'a> {
     type Error = ProgramError;
 
     fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
-        let [taker, maker, escrow, mint_a, mint_b, vault, taker_ata_a, taker_ata_b, maker_ata_b, system_program, token_program, _] =
+        let [taker, maker, escrow, mint_a, mint_b, vault, taker_ata_a, taker_ata_b, maker_ata_b, rent_destination, system_program, token_program, associated_token_program, remaining @ ..] =
             accounts
         else {
-            return Err(ProgramError::NotEnoughAccountKeys);
+            return Err(not_enough_account_keys(
+                "take",
+                TAKE_ACCOUNT_ORDER.len(),
+                accounts.len(),
+            ));
         };
 
         SignerAccount::check(taker)?;
+        validate_token_program(token_program)?;
+        validate_associated_token_program(associated_token_program)?;
         MintInterface::check(mint_a)?;
         MintInterface::check(mint_b)?;
         ProgramAccount::check(escrow)?;
         AssociatedTokenAccount::check(taker_ata_b, taker, mint_b, token_program)?;
-        AssociatedTokenAccount::check(vault, escrow, mint_a, token_program)?;
+        // vault 的地址在 Take::try_from 中通过 DerivedAddresses 校验
+        TokenAccountInterface::check(vault)?;
+        // taker 可能需要支付 ATA 的租金, maker 接收 vault 的租金
+        WritableAccount::check(taker)?;
+        WritableAccount::check(maker)?;
+        WritableAccount::check(escrow)?;
+        WritableAccount::check(vault)?;
+        WritableAccount::check(taker_ata_a)?;
+        WritableAccount::check(taker_ata_b)?;
+        WritableAccount::check(maker_ata_b)?;
+
+        // rent destination 可以是任意账户 (比如垫付租金的 relayer)
+        // 但必须可写, 并且不能是即将被关闭的 escrow 和 vault
+        WritableAccount::check(rent_destination)?;
+        if rent_destination.address() == escrow.address()
+            || rent_destination.address() == vault.address()
+        {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        // taker 和 maker 不能是同一个账户
+        // vault 和 escrow 不能作为转账的目标账户, token 账户之间也不能重复
+        // 特别是 taker_ata_b 和 maker_ata_b: 相同的话 token b 的转账是自己转给自己,
+        // taker 等于免费拿走了 token a
+        check_distinct(&[taker, maker, escrow])?;
+        check_distinct(&[escrow, vault, taker_ata_a, taker_ata_b, maker_ata_b])?;
+
+        // 可选的 stats 账户
+        let stats = remaining.first();
+        if let Some(stats) = stats {
+            StatsAccount::check(stats)?;
+        }
+
+        // 可选的 escrow 元数据账户, 跟在 stats 后面, 传入时和 escrow 一起关闭
+        let escrow_meta = remaining.get(1);
+        if let Some(escrow_meta) = escrow_meta {
+            EscrowMetaAccount::check(escrow_meta, escrow)?;
+        }
 
         Ok(Self {
             maker,
@@ -52,123 +119,988 @@ impl<'a> TryFrom<&'a [AccountView]> for TakeAccounts<'a> {
             maker_ata_b,
             taker_ata_b,
             taker_ata_a,
+            rent_destination,
             vault,
             token_program,
             system_program,
+            stats,
+            escrow_meta,
+        })
+    }
+}
+
+pub struct TakeInstructionData {
+    // 由谁支付 maker_ata_b 的创建费用 (如果需要创建的话)
+    // false: taker 支付 (默认), true: maker 支付, 此时 maker 必须签名
+    pub maker_funds_payout_ata: bool,
+    // taker 愿意支付的 token b 的最大数量, 0 表示不限制
+    // 防止 taker 模拟交易之后, escrow 的 receive 被提高
+    pub max_receive: u64,
+    // token a 是否转入调用方提供的任意 token account (可选字段, 默认 false)
+    // 开启后 taker_ata_a 可以是任意 owner 的 mint_a token account (比如 PDA 拥有的),
+    // 不要求是 taker 的 ATA, 也不会自动创建
+    pub custom_destination: bool,
+    // taker 自己设置的截止时间 (unix timestamp, 可选字段, 默认 0)
+    // 交易因为网络延迟很晚才上链时, 避免按照过时的条件成交, 0 表示不限制
+    pub valid_until: i64,
+    // 这次支付的 token b 数量 (可选字段, 默认 0 表示全部成交)
+    // 小于 escrow 的 receive 时是部分成交, 按比例转出 token a, escrow 保持打开
+    pub fill_amount: u64,
+}
+
+impl TakeInstructionData {
+    // maker_funds_payout_ata (1 byte) + max_receive (8 bytes)
+    pub const LEN: usize = size_of::<u8>() + size_of::<u64>();
+    // 可选字段: custom_destination (1 byte), valid_until (8 bytes), fill_amount (8 bytes)
+    // 可选字段按顺序出现, 传后面的字段时必须同时传前面的字段
+    pub const MAX_LEN: usize = Self::LEN + size_of::<u8>() + size_of::<i64>() + size_of::<u64>();
+}
+
+impl InstructionData for TakeInstructionData {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        // 没有数据时使用默认值, 兼容旧的客户端
+        if data.is_empty() {
+            return Ok(Self {
+                maker_funds_payout_ata: false,
+                max_receive: 0,
+                custom_destination: false,
+                valid_until: 0,
+                fill_amount: 0,
+            });
+        }
+
+        // 可选字段只能完整出现, 不能只传一部分
+        let valid_until_end = Self::LEN + size_of::<u8>() + size_of::<i64>();
+        if data.len() != Self::LEN
+            && data.len() != Self::LEN + 1
+            && data.len() != valid_until_end
+            && data.len() != Self::MAX_LEN
+        {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let maker_funds_payout_ata = match data[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+        let max_receive = u64::from_le_bytes(data[1..9].try_into().unwrap());
+        let custom_destination = match data.get(9) {
+            None | Some(0) => false,
+            Some(1) => true,
+            Some(_) => return Err(ProgramError::InvalidInstructionData),
+        };
+        let valid_until = match data.get(10..18) {
+            Some(bytes) => i64::from_le_bytes(bytes.try_into().unwrap()),
+            None => 0,
+        };
+        let fill_amount = match data.get(18..26) {
+            Some(bytes) => u64::from_le_bytes(bytes.try_into().unwrap()),
+            None => 0,
+        };
+
+        Ok(Self {
+            maker_funds_payout_ata,
+            max_receive,
+            custom_destination,
+            valid_until,
+            fill_amount,
         })
     }
 }
 
+// Take 的前置检查结果
+// 从 escrow 中复制出来, process 中使用时不需要再借用 escrow 账户
+pub struct TakeTerms {
+    // escrow 的 seed
+    pub seed: u64,
+    // escrow 当前剩余的 receive
+    pub receive: u64,
+    // escrow 当前记录的 token a 数量
+    pub amount: u64,
+    // taker 这次支付的 token b 数量, 等于 receive 时是全部成交
+    pub fill: u64,
+    // 缓存的派生地址
+    pub derived: DerivedAddresses,
+}
+
+// 部分成交之后的结果
+#[derive(Debug, PartialEq)]
+pub struct PartialFill {
+    // 转给 taker 的 token a 数量
+    pub token_a_out: u64,
+    // escrow 新的 receive
+    pub receive: u64,
+    // escrow 新的 amount
+    pub amount: u64,
+}
+
+// 计算部分成交: token_a_out = deposit * fill / receive, 向下取整
+// 取整的误差留在 vault 中, taker 拿到的 token a 不会超过他支付的 token b 对应的数量,
+// 剩下的 token a 和 receive 的比例也不会比原来更差
+// - fill 必须小于 receive, 等于时是全部成交, 不走这里
+// - 乘法在 u128 中计算, fill < receive 保证结果不超过 deposit
+pub fn partial_fill(
+    deposit: u64,
+    receive: u64,
+    amount: u64,
+    fill: u64,
+) -> Result<PartialFill, ProgramError> {
+    if fill >= receive {
+        return Err(EscrowError::PartialFillTooLarge.into());
+    }
+
+    let token_a_out = (deposit as u128 * fill as u128 / receive as u128) as u64;
+    // 支付的 token b 太少, 换不到任何 token a
+    if token_a_out == 0 {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    Ok(PartialFill {
+        token_a_out,
+        receive: receive - fill,
+        amount: amount.saturating_sub(token_a_out),
+    })
+}
+
 pub struct Take<'a> {
+    pub instruction_data: TakeInstructionData,
     pub accounts: TakeAccounts<'a>,
+    pub terms: TakeTerms,
 }
 
-impl<'a> TryFrom<&'a [AccountView]> for Take<'a> {
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for Take<'a> {
     type Error = ProgramError;
 
-    fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
         let accounts = TakeAccounts::try_from(accounts)?;
+        let instruction_data = TakeInstructionData::unpack(data)?;
+        let terms = Take::preflight(&accounts, &instruction_data, unix_timestamp()?)?;
 
-        // 为 taker 创建 token a 的 ata 账户(如果不存在)
-        AssociatedTokenAccount::init_if_needed(
-            accounts.taker_ata_a,
-            accounts.mint_a,
-            accounts.taker,
-            accounts.taker,
-            accounts.system_program,
-            accounts.token_program,
-        )?;
+        if instruction_data.custom_destination {
+            // 调用方提供的 token account, 只要求是 mint_a 的 token account, 不限制 owner
+            TokenAccountInterface::check(accounts.taker_ata_a)?;
+            if read_token_mint(accounts.taker_ata_a)?.ne(accounts.mint_a.address()) {
+                return Err(EscrowError::MintMismatch.into());
+            }
+        } else {
+            // 为 taker 创建 token a 的 ata 账户(如果不存在)
+            AssociatedTokenAccount::init_if_needed(
+                accounts.taker_ata_a,
+                accounts.mint_a,
+                accounts.taker,
+                accounts.taker,
+                accounts.system_program,
+                accounts.token_program,
+            )?;
+        }
 
         // 为 maker 创建 token b 的 ata 账户(如果不存在)
+        // 默认由 taker 支付, 也可以指定由 maker 自己支付, 避免 taker 替 maker 出租金
+        let payout_ata_payer = if instruction_data.maker_funds_payout_ata {
+            SignerAccount::check(accounts.maker)?;
+            accounts.maker
+        } else {
+            accounts.taker
+        };
         AssociatedTokenAccount::init_if_needed(
             accounts.maker_ata_b,
             accounts.mint_b,
-            accounts.taker,
+            payout_ata_payer,
             accounts.maker,
             accounts.system_program,
             accounts.token_program,
         )?;
 
-        Ok(Self { accounts })
+        Ok(Self {
+            instruction_data,
+            accounts,
+            terms,
+        })
     }
 }
 
 impl<'a> Take<'a> {
     pub const DISCRIMINATOR: &'a u8 = &1;
 
+    // Take 的全部前置检查, 集中在一起:
+    // taker 的截止时间, token program, mint, 滑点, escrow 和 vault 的地址
+    // 返回复制出来的 escrow 数据, 之后的 process 只需要按顺序执行转账
+    // now 是当前的 unix timestamp, 由 try_from 从 Clock 读取一次, 两个截止时间都用它判断
+    pub fn preflight(
+        accounts: &TakeAccounts<'a>,
+        instruction_data: &TakeInstructionData,
+        now: i64,
+    ) -> Result<TakeTerms, ProgramError> {
+        // taker 的截止时间
+        if deadline_passed_at(instruction_data.valid_until, now) {
+            return Err(EscrowError::Expired.into());
+        }
+
+        // 用调用指令所传入的账户中的 maker 账户和保存在 escrow 中的 seed 和 bump 计算 escrow pda 地址
+        // 同时计算 vault 的地址, 之后 process 中直接使用, 不再重复计算
+        // seed 和 receive 也一起复制出来, 离开 block 后 escrow 的借用守卫立即释放
+        let terms = {
+            let data = accounts.escrow.try_borrow()?;
+            let escrow = Escrow::load(&data)?;
+
+            // token_program 必须是 Make 时使用的 token program
+            if accounts.token_program.address().ne(&escrow.token_program) {
+                return Err(EscrowError::InvalidTokenProgram.into());
+            }
+
+            // mint_a 和 mint_b 必须和 escrow 中记录的一一对应
+            // 只校验两者都是合法的 mint 是不够的, 交换两个 mint 的位置会让
+            // vault (mint_a) 和 taker 的付款 (mint_b) 指向错误的 token
+            if accounts.mint_a.address() != &escrow.mint_a
+                || accounts.mint_b.address() != &escrow.mint_b
+            {
+                return Err(EscrowError::MintMismatch.into());
+            }
+
+            // maker 设置的过期时间, 0 表示永不过期
+            // 过期之后的 grace_seconds 秒内仍然可以成交 (grace fill), 超过宽限时间才返回 Expired
+            if deadline_passed_at(escrow.deadline, now) {
+                let grace_end = escrow
+                    .deadline
+                    .saturating_add(escrow.grace_seconds() as i64);
+                if deadline_passed_at(grace_end, now) {
+                    return Err(EscrowError::Expired.into());
+                }
+                log("escrow: grace fill");
+            }
+
+            // escrow 当前要求的 token b 数量不能超过 taker 的上限
+            if instruction_data.max_receive != 0 && escrow.receive > instruction_data.max_receive {
+                return Err(EscrowError::SlippageExceeded.into());
+            }
+
+            // 部分成交的数量不能超过剩余的 receive
+            if instruction_data.fill_amount > escrow.receive {
+                return Err(EscrowError::PartialFillTooLarge.into());
+            }
+            let fill = if instruction_data.fill_amount == 0 {
+                escrow.receive
+            } else {
+                instruction_data.fill_amount
+            };
+
+            // 全部成交会关闭 escrow, 有元数据账户时必须一起关闭, 否则它的租金再也无法取回
+            if fill == escrow.receive && escrow.has_meta() && accounts.escrow_meta.is_none() {
+                return Err(EscrowError::MetadataRequired.into());
+            }
+
+            TakeTerms {
+                seed: escrow.seed,
+                receive: escrow.receive,
+                amount: escrow.amount,
+                fill,
+                derived: DerivedAddresses::derive(
+                    accounts.escrow.address(),
+                    accounts.maker.address(),
+                    escrow.seed,
+                    escrow.bump[0],
+                    accounts.mint_a.address(),
+                    accounts.token_program.address(),
+                    escrow.vault_bump[0],
+                )?,
+            }
+        };
+
+        // 判断 vault 是否是 escrow 的 mint_a ATA
+        if accounts.vault.address() != &terms.derived.vault {
+            return Err(EscrowError::InvalidAddress.into());
+        }
+
+        Ok(terms)
+    }
+
+    // 执行顺序:
+    // 1. vault -> taker_ata_a 转出全部 token a, 然后关闭 vault
+    // 2. taker_ata_b -> maker_ata_b 转账 token b
+    // 3. 关闭 escrow
+    //
+    // 整个指令是原子的: 任何一步失败 (比如 taker 的 token b 余额不足), 所有修改都会回滚
+    // 包括已经执行过的 vault 转账和关闭, 所以 vault 和 escrow 不会停留在中间状态
+    // 先关闭 vault 再收款不会让 maker 承担风险
     pub fn process(&self) -> Result<(), ProgramError> {
-        let data = self.accounts.escrow.try_borrow()?;
-        let escrow = Escrow::load(data.as_ref())?;
-
-        // 判断 escrow 账户是否正确
-        // 用调用指令所传入的账户中的 maker 账户和保存在 escrow 中的 seed 和 bump 了计算 escrow pda 地址
-        // 通过计算出来的地址和指令账户列表中的 escrow 账户进行比较
-        let escrow_address = Address::create_program_address(
-            &[
-                b"escrow",
-                self.accounts.maker.address().as_ref(),
-                &escrow.seed.to_le_bytes(),
-                &escrow.bump,
-            ],
-            &crate::ID,
+        // escrow 的数据已经在 preflight 中复制出来, 后面的 CPI 和 close 期间都不持有 escrow 的借用
+        // try_borrow() 是运行时借用检查, 只要借用守卫还活着, 再次可变引用就会报错 (比如下面的 ProgramAccount::close)
+        let TakeTerms {
+            seed,
+            receive,
+            fill,
+            ref derived,
+            ..
+        } = self.terms;
+
+        // escrow 地址已经在 try_from 中校验过了
+        let seed_binding = seed.to_le_bytes();
+        let bump_binding = [derived.escrow_bump];
+        let escrow_seed =
+            escrow_signer(self.accounts.maker.address(), &seed_binding, &bump_binding);
+        let signers = &[Signer::from(&escrow_seed)];
+
+        // 任何转账之前确认存款和 receive 都不为 0
+        let deposit = read_token_amount(self.accounts.vault)?;
+        Escrow::check_settlement(deposit, receive)?;
+
+        if fill < receive {
+            return self.process_partial(deposit, receive, fill, signers);
+        }
+
+        // 从 vault 转账全部 token a 到 taker, 然后关闭 vault token account
+        // vault 的租金退还给 maker
+        drain_and_close_vault(
+            self.accounts.vault,
+            self.accounts.taker_ata_a,
+            self.accounts.maker,
+            self.accounts.escrow,
+            signers,
         )?;
-        if self.accounts.escrow.address() != &escrow_address {
-            return Err(ProgramError::InvalidAccountOwner);
+
+        self.pay_maker(receive)?;
+
+        if let Some(stats) = self.accounts.stats {
+            let mut stats_data = stats.try_borrow_mut()?;
+            Stats::load_mut(stats_data.as_mut())?.record_take(receive);
         }
 
-        let seed_binding = escrow.seed.to_le_bytes();
-        let escrow_seed = [
-            Seed::from(b"escrow"),
-            Seed::from(self.accounts.maker.address().as_ref()),
-            Seed::from(&seed_binding),
-            Seed::from(&escrow.bump),
-        ];
-        let signers = &[Signer::from(&escrow_seed)];
+        #[cfg(feature = "indexer-logs")]
+        crate::helpers::log_event("take", seed, deposit);
+
+        // 关闭 escrow 账户
+        // 这是关闭 escrow 数据账户
+        // 账户的 owner 从 system program 变为当前的 program
+        // 所以程序有权关闭它
+        // 租金转给 rent destination, 和 token a 的去向 (taker_ata_a) 分开
+        ProgramAccount::close(self.accounts.escrow, self.accounts.rent_destination)?;
+
+        // 元数据账户由 maker 创建, 租金退还给 maker
+        if let Some(escrow_meta) = self.accounts.escrow_meta {
+            ProgramAccount::close(escrow_meta, self.accounts.maker)?;
+        }
 
-        // 从 vault 转账 token a 到 taker
+        Ok(())
+    }
+
+    // 部分成交: 按比例转出 token a, 减少 escrow 的 receive 和 amount, escrow 和 vault 保持打开
+    // 数量的计算见 partial_fill
+    fn process_partial(
+        &self,
+        deposit: u64,
+        receive: u64,
+        fill: u64,
+        signers: &[Signer],
+    ) -> Result<(), ProgramError> {
+        let PartialFill {
+            token_a_out,
+            receive,
+            amount,
+        } = partial_fill(deposit, receive, self.terms.amount, fill)?;
+
+        check_transfer_authority(self.accounts.vault, self.accounts.escrow)?;
         Transfer {
             from: self.accounts.vault,
             to: self.accounts.taker_ata_a,
             authority: self.accounts.escrow,
-            amount: escrow.receive,
+            amount: token_a_out,
         }
         .invoke_signed(signers)?;
 
-        // 关闭 vault token account
-        // 这里关闭的是 token 账户, 他的 owner 是 token program
-        // 所以这里通过 CPI 调用 CloseAccount 方法, 通过 token program 来关闭 token account
-        // 并且通过 escrow pda 账户的签名证明有权关闭
-        CloseAccount {
-            account: self.accounts.vault,
-            destination: self.accounts.maker,
-            authority: self.accounts.escrow,
+        self.pay_maker(fill)?;
+
+        load_checked_mut(self.accounts.escrow, |escrow| {
+            escrow.set_receive(receive);
+            escrow.set_amount(amount);
+        })?;
+
+        if let Some(stats) = self.accounts.stats {
+            let mut stats_data = stats.try_borrow_mut()?;
+            Stats::load_mut(stats_data.as_mut())?.record_take(fill);
+        }
+
+        #[cfg(feature = "indexer-logs")]
+        crate::helpers::log_event("partial_take", self.terms.seed, token_a_out);
+
+        Ok(())
+    }
+
+    // 从 taker 转账 token b 到 maker
+    fn pay_maker(&self, amount: u64) -> Result<(), ProgramError> {
+        // token b 是 wrapped SOL 时, taker 可能是直接把 lamports 转进 wSOL 账户的
+        // 这时 token account 的 amount 还没有更新, 先 SyncNative 同步余额再转账
+        if self.accounts.mint_b.address() == &NATIVE_MINT
+            && self.accounts.token_program.address() == &pinocchio_token::ID
+        {
+            SyncNative {
+                native_token: self.accounts.taker_ata_b,
+            }
+            .invoke()?;
         }
-        .invoke_signed(signers)?;
 
-        // 从 taker 转账 token b 到 maker
+        check_transfer_authority(self.accounts.taker_ata_b, self.accounts.taker)?;
         Transfer {
             from: self.accounts.taker_ata_b,
             to: self.accounts.maker_ata_b,
             authority: self.accounts.taker,
-            amount: escrow.receive,
+            amount,
+        }
+        .invoke()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{ata_address, empty_accounts, escrow_address, views, TestAccount};
+    use pinocchio::Address;
+
+    // preflight 的测试场景, 默认是一个可以全部成交的合法 escrow
+    struct Case {
+        // escrow 中保存的数据
+        receive: u64,
+        deadline: i64,
+        grace_seconds: u32,
+        has_meta: bool,
+        // 保存一个错误的 bump
+        wrong_bump: bool,
+        // 传入的账户, 默认和 escrow 中保存的一致
+        maker: Address,
+        mint_a: Address,
+        mint_b: Address,
+        token_program: Address,
+        // 传入的 vault 地址, 默认是派生出的 vault
+        vault: Option<Address>,
+        // 指令数据
+        max_receive: u64,
+        valid_until: i64,
+        fill_amount: u64,
+        // 当前时间
+        now: i64,
+    }
+
+    impl Default for Case {
+        fn default() -> Self {
+            Self {
+                receive: 1_000,
+                deadline: 0,
+                grace_seconds: 0,
+                has_meta: false,
+                wrong_bump: false,
+                maker: Address::new_from_array([1; 32]),
+                mint_a: Address::new_from_array([2; 32]),
+                mint_b: Address::new_from_array([3; 32]),
+                token_program: pinocchio_token::ID,
+                vault: None,
+                max_receive: 0,
+                valid_until: 0,
+                fill_amount: 0,
+                now: 1_700_000_000,
+            }
         }
-        .invoke()?;
+    }
+
+    // 用真实派生的 escrow 和 vault 地址执行 preflight, 不传入元数据账户
+    fn preflight(case: Case) -> Result<TakeTerms, ProgramError> {
+        let seed = 42u64;
+        let maker = Address::new_from_array([1; 32]);
+        let mint_a = Address::new_from_array([2; 32]);
+        let mint_b = Address::new_from_array([3; 32]);
+        let (escrow, bump) = escrow_address(&maker, seed);
+        let (vault, vault_bump) = ata_address(&escrow, &mint_a);
 
-        // 这里不需要 escrow data 了, ProgramAccount::close 里需要引用它, 所以提前把它丢弃掉
-        // 因为 try_borrow() 是运行时借用检查, 它的类型是 Ref<[u8]>(类似 RefCell) (借用守卫)
-        // 内部持有一个借用计数器, 如果被引用后计数器 +1
-        // 如果不为 0 的话, 就证明有人在引用它, 所以再次可变引用就会报错
-        // 所以如果不提前释放的话, 下面的 ProgramAccount::close 会报错(内部需要了可变引用, 主要是可变引用)
-        //
-        // 借用守卫只在当前指令执行期间有效
+        let mut escrow = TestAccount::new(escrow, crate::ID, &[0; Escrow::LEN]);
+        let escrow = escrow.view();
+        let mut data = escrow.try_borrow_mut()?;
+        let state = Escrow::load_mut(&mut data)?;
+        state.set_inner(
+            seed,
+            maker,
+            mint_a,
+            mint_b,
+            case.receive,
+            100,
+            case.deadline,
+            [if case.wrong_bump { bump - 1 } else { bump }],
+            [vault_bump],
+            pinocchio_token::ID,
+        );
+        state.set_has_meta(case.has_meta);
+        state.set_grace_seconds(case.grace_seconds);
         drop(data);
 
-        // 关闭 escrow 账户
-        // 这是关闭 escrow 数据账户
-        // 账户的 owner 从 system program 变为当前的 program
-        // 所以程序有权关闭它
-        ProgramAccount::close(self.accounts.escrow, self.accounts.taker)?;
+        let mut maker = TestAccount::empty(case.maker);
+        let mut mint_a = TestAccount::empty(case.mint_a);
+        let mut mint_b = TestAccount::empty(case.mint_b);
+        let mut vault = TestAccount::empty(case.vault.unwrap_or(vault));
+        let mut token_program = TestAccount::empty(case.token_program);
+        let mut others = empty_accounts(6);
+        let others = views(&mut others);
+        let (maker, mint_a, mint_b, vault, token_program) = (
+            maker.view(),
+            mint_a.view(),
+            mint_b.view(),
+            vault.view(),
+            token_program.view(),
+        );
+        let accounts = TakeAccounts {
+            taker: &others[0],
+            maker: &maker,
+            escrow: &escrow,
+            mint_a: &mint_a,
+            mint_b: &mint_b,
+            vault: &vault,
+            taker_ata_a: &others[1],
+            taker_ata_b: &others[2],
+            maker_ata_b: &others[3],
+            rent_destination: &others[4],
+            system_program: &others[5],
+            token_program: &token_program,
+            stats: None,
+            escrow_meta: None,
+        };
+        let instruction_data = TakeInstructionData {
+            maker_funds_payout_ata: false,
+            max_receive: case.max_receive,
+            custom_destination: false,
+            valid_until: case.valid_until,
+            fill_amount: case.fill_amount,
+        };
 
-        Ok(())
+        Take::preflight(&accounts, &instruction_data, case.now)
+    }
+
+    #[test]
+    fn slippage_within_max() {
+        // 0 表示不限制
+        for max_receive in [0, 1_000, 1_001] {
+            let terms = preflight(Case {
+                max_receive,
+                ..Case::default()
+            })
+            .unwrap();
+            assert_eq!(terms.receive, 1_000);
+            assert_eq!(terms.fill, 1_000);
+        }
+    }
+
+    // 有元数据账户的 escrow, 全部成交时必须传入元数据账户, 部分成交不会关闭 escrow, 不需要
+    #[test]
+    fn full_fill_requires_metadata() {
+        for fill_amount in [0, 1_000] {
+            assert_eq!(
+                preflight(Case {
+                    fill_amount,
+                    has_meta: true,
+                    ..Case::default()
+                })
+                .err(),
+                Some(EscrowError::MetadataRequired.into())
+            );
+        }
+        assert!(preflight(Case {
+            fill_amount: 400,
+            has_meta: true,
+            ..Case::default()
+        })
+        .is_ok());
+        assert!(preflight(Case::default()).is_ok());
+    }
+
+    #[test]
+    fn slippage_above_max() {
+        assert_eq!(
+            preflight(Case {
+                max_receive: 999,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::SlippageExceeded.into())
+        );
+    }
+
+    #[test]
+    fn rejects_other_token_program() {
+        assert_eq!(
+            preflight(Case {
+                token_program: crate::helpers::TOKEN_2022_PROGRAM_ID,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::InvalidTokenProgram.into())
+        );
+    }
+
+    // 交换 mint_a 和 mint_b 的位置, 或者传入其他 mint
+    #[test]
+    fn rejects_swapped_mints() {
+        let default = Case::default();
+        for (mint_a, mint_b) in [
+            (default.mint_b.clone(), default.mint_a.clone()),
+            (default.mint_a.clone(), Address::new_from_array([9; 32])),
+        ] {
+            assert_eq!(
+                preflight(Case {
+                    mint_a,
+                    mint_b,
+                    ..Case::default()
+                })
+                .err(),
+                Some(EscrowError::MintMismatch.into())
+            );
+        }
+    }
+
+    // 用传入的 maker 和保存的 bump 派生出的地址必须是传入的 escrow
+    #[test]
+    fn rejects_wrong_escrow_pda() {
+        assert_eq!(
+            preflight(Case {
+                maker: Address::new_from_array([9; 32]),
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::InvalidEscrowPda.into())
+        );
+        assert_eq!(
+            preflight(Case {
+                wrong_bump: true,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::InvalidEscrowPda.into())
+        );
+    }
+
+    // 任意地址, 或者同一个 maker 的另一个 escrow 的 mint_a ATA
+    #[test]
+    fn rejects_other_vault() {
+        let default = Case::default();
+        let (other_escrow, _) = escrow_address(&default.maker, 43);
+        let (other_vault, _) = ata_address(&other_escrow, &default.mint_a);
+
+        for vault in [Address::new_from_array([9; 32]), other_vault] {
+            assert_eq!(
+                preflight(Case {
+                    vault: Some(vault),
+                    ..Case::default()
+                })
+                .err(),
+                Some(EscrowError::InvalidAddress.into())
+            );
+        }
+    }
+
+    #[test]
+    fn fill_larger_than_receive() {
+        assert_eq!(
+            preflight(Case {
+                fill_amount: 1_001,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::PartialFillTooLarge.into())
+        );
+
+        let terms = preflight(Case {
+            fill_amount: 999,
+            ..Case::default()
+        })
+        .unwrap();
+        assert_eq!(terms.fill, 999);
+    }
+
+    #[test]
+    fn partial_fill_rounds_down() {
+        // 100 * 1 / 3 = 33.3
+        assert_eq!(
+            partial_fill(100, 3, 100, 1),
+            Ok(PartialFill {
+                token_a_out: 33,
+                receive: 2,
+                amount: 67,
+            })
+        );
+
+        // 只剩 1 个 receive: 100 * 999 / 1000 = 99.9
+        assert_eq!(
+            partial_fill(100, 1_000, 100, 999),
+            Ok(PartialFill {
+                token_a_out: 99,
+                receive: 1,
+                amount: 1,
+            })
+        );
+
+        // 存款少于 receive: 10 * 150 / 1000 = 1.5
+        assert_eq!(
+            partial_fill(10, 1_000, 10, 150),
+            Ok(PartialFill {
+                token_a_out: 1,
+                receive: 850,
+                amount: 9,
+            })
+        );
+
+        // 乘法不会溢出
+        assert_eq!(
+            partial_fill(u64::MAX, u64::MAX, u64::MAX, u64::MAX - 1),
+            Ok(PartialFill {
+                token_a_out: u64::MAX - 1,
+                receive: 1,
+                amount: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn partial_fill_rejects_invalid_amounts() {
+        // 10 * 99 / 1000 = 0.99, 换不到任何 token a
+        assert_eq!(
+            partial_fill(10, 1_000, 10, 99),
+            Err(ProgramError::InvalidInstructionData)
+        );
+
+        // 全部成交或者超过 receive 不是部分成交
+        for fill in [1_000, 1_001] {
+            assert_eq!(
+                partial_fill(100, 1_000, 100, fill),
+                Err(EscrowError::PartialFillTooLarge.into())
+            );
+        }
+    }
+
+    // 同一个 maker 用不同的 seed 创建很多 escrow, 交替部分成交
+    // 每个 escrow 的地址, vault 和数据都是独立的, 修改一个不会影响其他的
+    #[test]
+    fn many_escrows_stay_independent() {
+        const COUNT: u64 = 48;
+        let maker = Address::new_from_array([1; 32]);
+        let mint_a = Address::new_from_array([2; 32]);
+        let mint_b = Address::new_from_array([3; 32]);
+
+        let addresses: Vec<_> = (0..COUNT)
+            .map(|seed| {
+                let (escrow, bump) = escrow_address(&maker, seed);
+                let (vault, vault_bump) = ata_address(&escrow, &mint_a);
+                (escrow, bump, vault, vault_bump)
+            })
+            .collect();
+        let mut escrows: Vec<_> = addresses
+            .iter()
+            .enumerate()
+            .map(|(seed, (escrow, bump, _, vault_bump))| {
+                let mut account = TestAccount::new(escrow.clone(), crate::ID, &[0; Escrow::LEN]);
+                Escrow::load_mut(&mut account.view().try_borrow_mut().unwrap())
+                    .unwrap()
+                    .set_inner(
+                        seed as u64,
+                        maker.clone(),
+                        mint_a.clone(),
+                        mint_b.clone(),
+                        1_000 + seed as u64,
+                        100 + seed as u64,
+                        0,
+                        [*bump],
+                        [*vault_bump],
+                        pinocchio_token::ID,
+                    );
+                account
+            })
+            .collect();
+        let escrows = views(&mut escrows);
+
+        // 三轮, 每轮按照不同的顺序给一部分 escrow 部分成交
+        let mut fills = vec![0u64; COUNT as usize];
+        for round in 0..3u64 {
+            for seed in (0..COUNT).filter(|seed| seed % 3 != round) {
+                let seed = if round == 1 { COUNT - 1 - seed } else { seed };
+                let mut data = escrows[seed as usize].try_borrow_mut().unwrap();
+                let escrow = Escrow::load_mut(&mut data).unwrap();
+                let fill = partial_fill(escrow.amount, escrow.receive, escrow.amount, 10).unwrap();
+                escrow.set_receive(fill.receive);
+                escrow.set_amount(fill.amount);
+                fills[seed as usize] += 1;
+            }
+        }
+
+        for (seed, (address, bump, vault, vault_bump)) in addresses.iter().enumerate() {
+            let data = escrows[seed].try_borrow().unwrap();
+            let escrow = Escrow::load(&data).unwrap();
+
+            // 每次成交从 receive 中减去 10, amount 按比例向下取整减少
+            let (mut receive, mut amount) = (1_000 + seed as u64, 100 + seed as u64);
+            for _ in 0..fills[seed] {
+                amount -= amount * 10 / receive;
+                receive -= 10;
+            }
+            assert_eq!(escrow.seed(), seed as u64);
+            assert_eq!(escrow.receive(), receive);
+            assert_eq!(escrow.amount(), amount);
+
+            // 用自己保存的 bump 派生出自己的地址和 vault
+            let derived = DerivedAddresses::derive(
+                address,
+                &maker,
+                seed as u64,
+                *bump,
+                &mint_a,
+                &pinocchio_token::ID,
+                *vault_bump,
+            )
+            .unwrap();
+            assert_eq!(&derived.vault, vault);
+
+            // 不能被当作相邻的 escrow 使用
+            let (other, ..) = &addresses[(seed + 1) % COUNT as usize];
+            assert_eq!(
+                DerivedAddresses::derive(
+                    other,
+                    &maker,
+                    seed as u64,
+                    *bump,
+                    &mint_a,
+                    &pinocchio_token::ID,
+                    *vault_bump,
+                )
+                .err(),
+                Some(EscrowError::InvalidEscrowPda.into())
+            );
+        }
+
+        // 所有 escrow 和 vault 的地址互不相同
+        let mut all: Vec<_> = addresses
+            .iter()
+            .flat_map(|(escrow, _, vault, _)| [escrow.clone(), vault.clone()])
+            .collect();
+        all.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        all.dedup();
+        assert_eq!(all.len(), 2 * COUNT as usize);
+    }
+
+    // maker 的过期时间和 taker 的 valid_until 过了之后都返回 Expired
+    #[test]
+    fn expired() {
+        let now = Case::default().now;
+
+        assert_eq!(
+            preflight(Case {
+                deadline: now - 1,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::Expired.into())
+        );
+        assert_eq!(
+            preflight(Case {
+                valid_until: now - 1,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::Expired.into())
+        );
+
+        // 宽限时间内还可以成交, 包括宽限时间的最后一秒
+        for deadline in [now - 1, now - 600] {
+            assert!(preflight(Case {
+                deadline,
+                grace_seconds: 600,
+                ..Case::default()
+            })
+            .is_ok());
+        }
+        assert_eq!(
+            preflight(Case {
+                deadline: now - 601,
+                grace_seconds: 600,
+                ..Case::default()
+            })
+            .err(),
+            Some(EscrowError::Expired.into())
+        );
+
+        // 正好等于截止时间时还可以成交
+        assert!(preflight(Case {
+            deadline: now,
+            valid_until: now,
+            ..Case::default()
+        })
+        .is_ok());
+    }
+
+    // TAKE_ACCOUNT_ORDER 的长度必须和 try_from 中解构的账户数量一致
+    // 少一个账户时报账户数量不足, 数量正好时进入后面的账户校验
+    #[test]
+    fn account_order_matches_try_from() {
+        let mut accounts = empty_accounts(TAKE_ACCOUNT_ORDER.len());
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            TakeAccounts::try_from(&accounts[..TAKE_ACCOUNT_ORDER.len() - 1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_eq!(
+            TakeAccounts::try_from(&accounts[..]).err(),
+            Some(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn unpack_empty_data_uses_defaults() {
+        let ix = TakeInstructionData::unpack(&[]).unwrap();
+        assert!(!ix.maker_funds_payout_ata);
+        assert_eq!(ix.max_receive, 0);
+        assert!(!ix.custom_destination);
+        assert_eq!(ix.valid_until, 0);
+        assert_eq!(ix.fill_amount, 0);
+    }
+
+    #[test]
+    fn unpack_all_fields() {
+        let mut data = [0u8; TakeInstructionData::MAX_LEN];
+        data[0] = 1;
+        data[1..9].copy_from_slice(&500u64.to_le_bytes());
+        data[9] = 1;
+        data[10..18].copy_from_slice(&1_700_000_000i64.to_le_bytes());
+        data[18..26].copy_from_slice(&20u64.to_le_bytes());
+
+        let ix = TakeInstructionData::unpack(&data).unwrap();
+        assert!(ix.maker_funds_payout_ata);
+        assert_eq!(ix.max_receive, 500);
+        assert!(ix.custom_destination);
+        assert_eq!(ix.valid_until, 1_700_000_000);
+        assert_eq!(ix.fill_amount, 20);
+
+        // 只传必须的字段时, 可选字段使用默认值
+        let ix = TakeInstructionData::unpack(&data[..TakeInstructionData::LEN]).unwrap();
+        assert_eq!(ix.max_receive, 500);
+        assert!(!ix.custom_destination);
+        assert_eq!(ix.fill_amount, 0);
+    }
+
+    #[test]
+    fn unpack_rejects_invalid_data() {
+        let data = [0u8; TakeInstructionData::MAX_LEN + 1];
+        for len in [
+            1,
+            TakeInstructionData::LEN - 1,
+            TakeInstructionData::LEN + 2,
+            TakeInstructionData::MAX_LEN - 1,
+            TakeInstructionData::MAX_LEN + 1,
+        ] {
+            assert_eq!(
+                TakeInstructionData::unpack(&data[..len]).err(),
+                Some(ProgramError::InvalidInstructionData)
+            );
+        }
+
+        // bool 字段不是 0 或 1
+        let mut data = [0u8; TakeInstructionData::LEN + 1];
+        data[0] = 2;
+        assert!(TakeInstructionData::unpack(&data).is_err());
+        data[0] = 0;
+        data[9] = 2;
+        assert!(TakeInstructionData::unpack(&data).is_err());
     }
 }
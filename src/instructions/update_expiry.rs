@@ -0,0 +1,214 @@
+// maker 修改 escrow 的过期时间, maker 签名
+// 延长和缩短都可以, 但是有范围限制:
+// - 缩短时新的过期时间至少在 MIN_NOTICE_SECONDS 之后, 正在考虑成交的 taker 不会突然看到 escrow 过期
+// - 新的过期时间最多在 MAX_EXPIRY_SECONDS 之后, 也不能改成 0 (永不过期)
+// 宽限时间 (grace_seconds) 保持不变
+use crate::{
+    errors::EscrowError,
+    helpers::{
+        load_checked_mut, not_enough_account_keys, unix_timestamp, AccountCheck, InstructionData,
+        ProgramAccount, SignerAccount, WritableAccount,
+    },
+    state::Escrow,
+};
+use pinocchio::{error::ProgramError, AccountView, ProgramResult};
+
+pub struct UpdateExpiryAccounts<'a> {
+    // escrow 的创建者
+    pub maker: &'a AccountView,
+    pub escrow: &'a AccountView,
+}
+
+impl<'a> TryFrom<&'a [AccountView]> for UpdateExpiryAccounts<'a> {
+    type Error = ProgramError;
+
+    fn try_from(accounts: &'a [AccountView]) -> Result<Self, Self::Error> {
+        let [maker, escrow, ..] = accounts else {
+            return Err(not_enough_account_keys("update_expiry", 2, accounts.len()));
+        };
+
+        SignerAccount::check(maker)?;
+        ProgramAccount::check(escrow)?;
+        WritableAccount::check(escrow)?;
+
+        // 只有 escrow 的 maker 可以修改过期时间
+        {
+            let data = escrow.try_borrow()?;
+            if Escrow::load(&data)?.maker.ne(maker.address()) {
+                return Err(EscrowError::InvalidAddress.into());
+            }
+        }
+
+        Ok(Self { maker, escrow })
+    }
+}
+
+pub struct UpdateExpiryInstructionData {
+    // 新的过期时间 (unix timestamp)
+    pub deadline: i64,
+}
+
+impl UpdateExpiryInstructionData {
+    // deadline (8 bytes)
+    pub const LEN: usize = size_of::<i64>();
+}
+
+impl InstructionData for UpdateExpiryInstructionData {
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() != Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let deadline = i64::from_le_bytes(data.try_into().unwrap());
+        if deadline < 0 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self { deadline })
+    }
+}
+
+pub struct UpdateExpiry<'a> {
+    pub instruction_data: UpdateExpiryInstructionData,
+    pub accounts: UpdateExpiryAccounts<'a>,
+}
+
+impl<'a> TryFrom<(&'a [u8], &'a [AccountView])> for UpdateExpiry<'a> {
+    type Error = ProgramError;
+
+    fn try_from((data, accounts): (&'a [u8], &'a [AccountView])) -> Result<Self, Self::Error> {
+        let accounts = UpdateExpiryAccounts::try_from(accounts)?;
+        let instruction_data = UpdateExpiryInstructionData::unpack(data)?;
+
+        let current = {
+            let data = accounts.escrow.try_borrow()?;
+            Escrow::load(&data)?.deadline
+        };
+        UpdateExpiry::check_bounds(current, instruction_data.deadline, unix_timestamp()?)?;
+
+        Ok(Self {
+            instruction_data,
+            accounts,
+        })
+    }
+}
+
+impl<'a> UpdateExpiry<'a> {
+    pub const DISCRIMINATOR: &'a u8 = &10;
+
+    // 缩短过期时间时, 新的过期时间距离现在至少要有这么多秒
+    pub const MIN_NOTICE_SECONDS: i64 = 10 * 60;
+
+    // 新的过期时间距离现在最多这么多秒
+    pub const MAX_EXPIRY_SECONDS: i64 = 365 * 24 * 60 * 60;
+
+    // 校验新的过期时间, 超出范围时返回 UpdateOutOfBounds
+    // - current 为 0 (永不过期) 时, 设置任何过期时间都是缩短
+    // - new 为 0 表示永不过期, 超过了最大范围
+    pub fn check_bounds(current: i64, new: i64, now: i64) -> ProgramResult {
+        if new == 0 || new > now.saturating_add(Self::MAX_EXPIRY_SECONDS) {
+            return Err(EscrowError::UpdateOutOfBounds.into());
+        }
+
+        let shortened = current == 0 || new < current;
+        if shortened && new < now.saturating_add(Self::MIN_NOTICE_SECONDS) {
+            return Err(EscrowError::UpdateOutOfBounds.into());
+        }
+
+        Ok(())
+    }
+
+    pub fn process(&self) -> Result<(), ProgramError> {
+        load_checked_mut(self.accounts.escrow, |escrow| {
+            escrow.set_deadline(self.instruction_data.deadline)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{empty_accounts, views};
+
+    const NOW: i64 = 1_700_000_000;
+    const HOUR: i64 = 60 * 60;
+
+    // 账户不足时报账户数量不足, 多出来的账户不影响解构
+    #[test]
+    fn account_count() {
+        let mut accounts = empty_accounts(2 + 1);
+        let accounts = views(&mut accounts);
+
+        assert_eq!(
+            UpdateExpiryAccounts::try_from(&accounts[..2 - 1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_ne!(
+            UpdateExpiryAccounts::try_from(&accounts[..]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn extend_within_bounds() {
+        assert!(UpdateExpiry::check_bounds(NOW + HOUR, NOW + 2 * HOUR, NOW).is_ok());
+        assert!(UpdateExpiry::check_bounds(
+            NOW + HOUR,
+            NOW + UpdateExpiry::MAX_EXPIRY_SECONDS,
+            NOW
+        )
+        .is_ok());
+
+        // 已经过期的 escrow 也可以重新设置过期时间
+        assert!(UpdateExpiry::check_bounds(NOW - HOUR, NOW + HOUR, NOW).is_ok());
+    }
+
+    #[test]
+    fn shorten_with_notice() {
+        assert!(UpdateExpiry::check_bounds(
+            NOW + 2 * HOUR,
+            NOW + UpdateExpiry::MIN_NOTICE_SECONDS,
+            NOW
+        )
+        .is_ok());
+        // 永不过期的 escrow 设置过期时间
+        assert!(UpdateExpiry::check_bounds(0, NOW + HOUR, NOW).is_ok());
+    }
+
+    #[test]
+    fn shorten_too_aggressively() {
+        for (current, new) in [
+            (NOW + 2 * HOUR, NOW + UpdateExpiry::MIN_NOTICE_SECONDS - 1),
+            (NOW + 2 * HOUR, NOW - 1),
+            (0, NOW + 1),
+        ] {
+            assert_eq!(
+                UpdateExpiry::check_bounds(current, new, NOW).err(),
+                Some(EscrowError::UpdateOutOfBounds.into())
+            );
+        }
+    }
+
+    #[test]
+    fn extend_past_max() {
+        for new in [NOW + UpdateExpiry::MAX_EXPIRY_SECONDS + 1, i64::MAX, 0] {
+            assert_eq!(
+                UpdateExpiry::check_bounds(NOW + HOUR, new, NOW).err(),
+                Some(EscrowError::UpdateOutOfBounds.into())
+            );
+        }
+    }
+
+    #[test]
+    fn unpack_deadline() {
+        let ix = UpdateExpiryInstructionData::unpack(&NOW.to_le_bytes()).unwrap();
+        assert_eq!(ix.deadline, NOW);
+
+        for data in [&[][..], &NOW.to_le_bytes()[..7], &(-1i64).to_le_bytes()] {
+            assert_eq!(
+                UpdateExpiryInstructionData::unpack(data).err(),
+                Some(ProgramError::InvalidInstructionData)
+            );
+        }
+    }
+}
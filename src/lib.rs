@@ -1,4 +1,9 @@
-#![no_std]
+// 链上程序 (默认 feature) 必须保持 no_std 并且不分配堆内存:
+// 没有 `extern crate alloc`, 所以 Vec / String / format! 等在默认 feature 下无法编译
+// 需要堆内存的代码只能放在 std feature 后面, 给链下客户端使用
+// scripts/check-features.sh 会检查这一点
+// 单元测试运行在宿主机上, 使用 std
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
 use pinocchio::{
     address::{declare_id, Address},
@@ -14,20 +19,79 @@ pub mod errors;
 pub mod helpers;
 pub mod instructions;
 pub mod state;
+#[cfg(test)]
+mod test_utils;
 
 pub use instructions::*;
 
 declare_id!("22222222222222222222222222222222222222222222");
 
+// 编译期检查所有指令的 discriminator 从 0 开始连续并且互不相同
+// 新增指令时把它加到数组末尾, 重复或者跳过的 discriminator 会导致编译失败
+const _: () = {
+    let discriminators = [
+        *Make::DISCRIMINATOR,
+        *Take::DISCRIMINATOR,
+        *Refund::DISCRIMINATOR,
+        *InitStats::DISCRIMINATOR,
+        *BatchRefund::DISCRIMINATOR,
+        *GetEscrow::DISCRIMINATOR,
+        *RepairEscrow::DISCRIMINATOR,
+        *SetEscrowMeta::DISCRIMINATOR,
+        *SimulateMake::DISCRIMINATOR,
+        *SplitEscrow::DISCRIMINATOR,
+        *UpdateExpiry::DISCRIMINATOR,
+    ];
+
+    let mut i = 0;
+    while i < discriminators.len() {
+        assert!(
+            discriminators[i] as usize == i,
+            "instruction discriminators must be unique and contiguous"
+        );
+        i += 1;
+    }
+};
+
+// 指令数据 (包括 discriminator) 的最大长度
+// 目前最长的是 SetEscrowMeta (1 + EscrowMeta::MAX_URI_LEN), 超过这个长度的数据直接拒绝, 不进入各个指令的解析
+pub const MAX_INSTRUCTION_DATA_LEN: usize = 256;
+
+const _: () = assert!(state::EscrowMeta::MAX_URI_LEN < MAX_INSTRUCTION_DATA_LEN);
+
 fn process_instruction(
     _program_id: &Address,
     accounts: &[AccountView],
     instruction_data: &[u8],
 ) -> ProgramResult {
+    if instruction_data.len() > MAX_INSTRUCTION_DATA_LEN {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
     match instruction_data.split_first() {
         Some((Make::DISCRIMINATOR, data)) => Make::try_from((data, accounts))?.process(),
-        Some((Take::DISCRIMINATOR, _)) => Take::try_from(accounts)?.process(),
-        Some((Refund::DISCRIMINATOR, _)) => Refund::try_from(accounts)?.process(),
+        Some((Take::DISCRIMINATOR, data)) => Take::try_from((data, accounts))?.process(),
+        Some((Refund::DISCRIMINATOR, data)) => Refund::try_from((data, accounts))?.process(),
+        Some((InitStats::DISCRIMINATOR, data)) => InitStats::try_from((data, accounts))?.process(),
+        Some((BatchRefund::DISCRIMINATOR, data)) => {
+            BatchRefund::try_from((data, accounts))?.process()
+        }
+        Some((GetEscrow::DISCRIMINATOR, data)) => GetEscrow::try_from((data, accounts))?.process(),
+        Some((RepairEscrow::DISCRIMINATOR, data)) => {
+            RepairEscrow::try_from((data, accounts))?.process()
+        }
+        Some((SetEscrowMeta::DISCRIMINATOR, data)) => {
+            SetEscrowMeta::try_from((data, accounts))?.process()
+        }
+        Some((SimulateMake::DISCRIMINATOR, data)) => {
+            SimulateMake::try_from((data, accounts))?.process()
+        }
+        Some((SplitEscrow::DISCRIMINATOR, data)) => {
+            SplitEscrow::try_from((data, accounts))?.process()
+        }
+        Some((UpdateExpiry::DISCRIMINATOR, data)) => {
+            UpdateExpiry::try_from((data, accounts))?.process()
+        }
         _ => Err(ProgramError::InvalidInstructionData),
     }
 }
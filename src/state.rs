@@ -1,5 +1,9 @@
+use crate::errors::EscrowError;
 use pinocchio::{error::ProgramError, Address};
 
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+
 // Pinocchio 中的 instruction data 是连续的
 // 而结构体的总大小必须是其最大字段的对齐要求的倍数
 // 所以须要手动定义结构体字段的顺序, 从大到小依次往下排列.
@@ -16,37 +20,184 @@ pub struct Escrow {
     pub mint_b: Address,
     // 希望接收的 token b 的数量
     pub receive: u64,
+    // maker 存入的 token a 的数量, 在 Make 中保存
+    // 以这里为准, 而不是从 vault 的余额推断 (收取转账手续费的 mint 会让 vault 实际收到的少于 amount)
+    pub amount: u64,
+    // escrow 的过期时间 (unix timestamp), 超过之后 Take 会失败, 0 表示永不过期
+    // 设置了 grace_seconds 时, 超过之后的 grace_seconds 秒内 Take 仍然可以成交
+    pub deadline: i64,
     // 缓存的 bump (bumps 更合适, 但是这里和 blueshift 官方教程保持一致吧)
     pub bump: [u8; 1],
+    // vault ATA 的 canonical bump, 在 Make 中计算并保存
+    // Take 和 Refund 直接用它通过 create_program_address 校验 vault, 不需要再 find_program_address
+    pub vault_bump: [u8; 1],
+    // Make 时使用的 token program (spl token 或 token 2022)
+    // 所有指令只传一个 token_program 账户, 两个 mint 必须由同一个 token program 管理
+    // Take 和 Refund 校验传入的 token_program 和这里保存的一致
+    pub token_program: Address,
+    // 是否创建了元数据账户 (0 或 1), 由 SetEscrowMeta 设置
+    // 关闭 escrow 的指令 (Take, Refund, RepairEscrow) 看到它时必须传入元数据账户, 和 escrow 一起关闭
+    pub has_meta: [u8; 1],
+    // 过期之后的宽限时间 (秒, u32 小端序), 由 Make 设置, 0 表示没有宽限
+    // 用字节数组保存, 不影响结构体的对齐
+    pub grace_seconds: [u8; 4],
+    // 显式的填充字节, 始终为 0, 之后新增字段时可以使用
+    // 没有它的话 #[repr(C)] 会因为 u64 的 8 字节对齐在末尾隐式填充, size_of::<Escrow>() 和 LEN 不一致
+    pub _reserved: [u8; 1],
+}
+
+// 创建账户 (ProgramAccount::init), 校验 (ProgramAccount::check) 和 load 都使用 Escrow::LEN
+// 这里保证它和结构体的实际大小一致, 字段变化导致不一致时编译失败
+const _: () = assert!(size_of::<Escrow>() == Escrow::LEN);
+
+// load / load_mut 把账户数据直接转换为结构体引用之前的检查, 相当于 bytemuck::try_from_bytes
+// - 长度必须正好等于结构体的大小 (LEN 和 size_of 一致, 见上面的编译期断言)
+// - 起始地址必须满足结构体的对齐 (u64 需要 8 字节对齐)
+//   运行时传入的账户数据一般是对齐的, 但 CPI 中重新布局的缓冲区不保证, 未对齐时直接转换是未定义行为
+// Escrow 和 Stats 只包含整数和字节数组, 并且没有隐式填充, 任意字节都是合法的值
+#[inline(always)]
+fn check_layout<T>(bytes: &[u8]) -> Result<(), ProgramError> {
+    if bytes.len() != size_of::<T>() || bytes.as_ptr().align_offset(align_of::<T>()) != 0 {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
 }
 
 // 实现 Escrow 结构体, 自定义一些方法
 impl Escrow {
+    // escrow PDA 的种子前缀, seeds = [b"escrow", maker, seed_le]
+    pub const SEED: &'static [u8] = b"escrow";
+
+    // 保留给协议以后使用的 seed 范围 [u64::MAX - 255, u64::MAX]
+    // 避免 maker 创建的 escrow PDA 和以后的协议账户冲突, Make 会拒绝这个范围内的 seed
+    pub const RESERVED_SEEDS: core::ops::RangeInclusive<u64> = u64::MAX - 255..=u64::MAX;
+
+    // 结算时 vault 中的存款和 receive 的最小值
+    pub const MIN_SETTLEMENT_AMOUNT: u64 = 1;
+
     // 计算 Escrow 结构体的大小 bytes
     pub const LEN: usize = size_of::<u64>() // 8 bytes (seed)
         + size_of::<Address>() // 32 bytes (maker)
         + size_of::<Address>() // 32 bytes (mint_a)
         + size_of::<Address>() // 32 bytes (mint_b)
         + size_of::<u64>() // 8 bytes (receive)
-        + size_of::<[u8; 1]>(); // 1 bytes (bump)
+        + size_of::<u64>() // 8 bytes (amount)
+        + size_of::<i64>() // 8 bytes (deadline)
+        + size_of::<[u8; 1]>() // 1 bytes (bump)
+        + size_of::<[u8; 1]>() // 1 bytes (vault_bump)
+        + size_of::<Address>() // 32 bytes (token_program)
+        + size_of::<[u8; 1]>() // 1 bytes (has_meta)
+        + size_of::<[u8; 4]>() // 4 bytes (grace_seconds)
+        + size_of::<[u8; 1]>(); // 1 bytes (_reserved)
 
     // inline(always) 用于在调用处展开函数代码块, 减少 CU 的消耗
     // 将原始字节指针转换为 Escrow 结构体的可变引用
     #[inline(always)]
     pub fn load_mut(bytes: &mut [u8]) -> Result<&mut Self, ProgramError> {
-        if bytes.len() != Escrow::LEN {
-            return Err(ProgramError::InvalidAccountData);
-        }
-        Ok(unsafe { &mut *core::mem::transmute::<*mut u8, *mut Self>(bytes.as_mut_ptr()) })
+        check_layout::<Self>(bytes)?;
+        Ok(unsafe { &mut *(bytes.as_mut_ptr() as *mut Self) })
     }
 
-    // 功能E-Business load_mut 一样, 只是得到的是不可变引用
+    // 功能和 load_mut 一样, 只是得到的是不可变引用
     #[inline(always)]
     pub fn load(bytes: &[u8]) -> Result<&Self, ProgramError> {
-        if bytes.len() != Escrow::LEN {
-            return Err(ProgramError::InvalidAccountData);
+        check_layout::<Self>(bytes)?;
+        Ok(unsafe { &*(bytes.as_ptr() as *const Self) })
+    }
+
+    // 用保存的 bump 计算 escrow PDA 地址, seeds = [b"escrow", maker, seed_le, bump]
+    // bump 已知, 所以用 create_program_address, 比 find_program_address 便宜
+    // Take, Refund 等指令都通过 DerivedAddresses::derive 调用它, 然后和传入的 escrow 账户比较
+    #[inline(always)]
+    pub fn derive_address(maker: &Address, seed: u64, bump: u8) -> Result<Address, ProgramError> {
+        Address::create_program_address(
+            &[Self::SEED, maker.as_ref(), &seed.to_le_bytes(), &[bump]],
+            &crate::ID,
+        )
+        .map_err(|_| EscrowError::InvalidEscrowPda.into())
+    }
+
+    // 结算前的最后一道检查: vault 中的存款和 receive 都不能低于 MIN_SETTLEMENT_AMOUNT
+    // 任何一个为 0 都说明上游出了问题 (迁移出错, 部分成交的计算错误等)
+    // 继续结算会让 taker 免费拿走 token a, 或者 maker 的 token a 被 0 对价换走
+    #[inline(always)]
+    pub fn check_settlement(deposit: u64, receive: u64) -> Result<(), ProgramError> {
+        if deposit < Self::MIN_SETTLEMENT_AMOUNT || receive < Self::MIN_SETTLEMENT_AMOUNT {
+            return Err(EscrowError::InvalidAccountData.into());
         }
-        Ok(unsafe { &*core::mem::transmute::<*const u8, *const Self>(bytes.as_ptr()) })
+        Ok(())
+    }
+
+    // 读取 seed 字段
+    #[inline(always)]
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // 读取 maker 字段
+    #[inline(always)]
+    pub fn maker(&self) -> &Address {
+        &self.maker
+    }
+
+    // 读取 mint_a 字段
+    #[inline(always)]
+    pub fn mint_a(&self) -> &Address {
+        &self.mint_a
+    }
+
+    // 读取 mint_b 字段
+    #[inline(always)]
+    pub fn mint_b(&self) -> &Address {
+        &self.mint_b
+    }
+
+    // 读取 receive 字段
+    #[inline(always)]
+    pub fn receive(&self) -> u64 {
+        self.receive
+    }
+
+    // 读取 amount 字段
+    #[inline(always)]
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+
+    // 读取 deadline 字段
+    #[inline(always)]
+    pub fn deadline(&self) -> i64 {
+        self.deadline
+    }
+
+    // 读取 bump 字段
+    #[inline(always)]
+    pub fn bump(&self) -> u8 {
+        self.bump[0]
+    }
+
+    // 读取 vault_bump 字段
+    #[inline(always)]
+    pub fn vault_bump(&self) -> u8 {
+        self.vault_bump[0]
+    }
+
+    // 读取 token_program 字段
+    #[inline(always)]
+    pub fn token_program(&self) -> &Address {
+        &self.token_program
+    }
+
+    // 读取 has_meta 字段
+    #[inline(always)]
+    pub fn has_meta(&self) -> bool {
+        self.has_meta[0] != 0
+    }
+
+    // 读取 grace_seconds 字段
+    #[inline(always)]
+    pub fn grace_seconds(&self) -> u32 {
+        u32::from_le_bytes(self.grace_seconds)
     }
 
     // 设置 seed 字段
@@ -79,14 +230,70 @@ impl Escrow {
         self.receive = receive;
     }
 
+    // 设置 amount 字段
+    #[inline(always)]
+    pub fn set_amount(&mut self, amount: u64) {
+        self.amount = amount;
+    }
+
+    // 设置 deadline 字段
+    #[inline(always)]
+    pub fn set_deadline(&mut self, deadline: i64) {
+        self.deadline = deadline;
+    }
+
     // 设置 bump 字段
     #[inline(always)]
     pub fn set_bump(&mut self, bump: [u8; 1]) {
         self.bump = bump;
     }
 
+    // 设置 vault_bump 字段
+    #[inline(always)]
+    pub fn set_vault_bump(&mut self, vault_bump: [u8; 1]) {
+        self.vault_bump = vault_bump;
+    }
+
+    // 设置 token_program 字段
+    #[inline(always)]
+    pub fn set_token_program(&mut self, token_program: Address) {
+        self.token_program = token_program;
+    }
+
+    // 设置 has_meta 字段
+    #[inline(always)]
+    pub fn set_has_meta(&mut self, has_meta: bool) {
+        self.has_meta = [has_meta as u8];
+    }
+
+    // 设置 grace_seconds 字段
+    #[inline(always)]
+    pub fn set_grace_seconds(&mut self, grace_seconds: u32) {
+        self.grace_seconds = grace_seconds.to_le_bytes();
+    }
+
+    // 复制一份 escrow 数据的快照, 离开借用之后也可以使用
+    #[cfg(feature = "std")]
+    pub fn terms(&self) -> EscrowTerms {
+        EscrowTerms {
+            seed: self.seed,
+            maker: self.maker.to_bytes(),
+            mint_a: self.mint_a.to_bytes(),
+            mint_b: self.mint_b.to_bytes(),
+            receive: self.receive,
+            amount: self.amount,
+            deadline: self.deadline,
+            bump: self.bump[0],
+            vault_bump: self.vault_bump[0],
+            token_program: self.token_program.to_bytes(),
+            has_meta: self.has_meta(),
+            grace_seconds: self.grace_seconds(),
+        }
+    }
+
     // 设置所有字段
     #[inline(always)]
+    #[allow(clippy::too_many_arguments)]
     pub fn set_inner(
         &mut self,
         seed: u64,
@@ -94,13 +301,380 @@ impl Escrow {
         mint_a: Address,
         mint_b: Address,
         receive: u64,
+        amount: u64,
+        deadline: i64,
         bump: [u8; 1],
+        vault_bump: [u8; 1],
+        token_program: Address,
     ) {
         self.seed = seed;
         self.maker = maker;
         self.mint_a = mint_a;
         self.mint_b = mint_b;
         self.receive = receive;
+        self.amount = amount;
+        self.deadline = deadline;
+        self.bump = bump;
+        self.vault_bump = vault_bump;
+        self.token_program = token_program;
+        // 新写入的 escrow 还没有元数据账户, 宽限时间需要的话之后单独设置
+        self.has_meta = [0];
+        self.grace_seconds = [0; 4];
+    }
+}
+
+// 给链下客户端和测试使用的 escrow 数据快照, 只在 std 下编译, borsh 序列化需要 borsh feature
+// 按照 Escrow 的 #[repr(C)] 布局逐个字段解析, 不依赖内存对齐
+// 地址使用 [u8; 32], 这样不需要 Address 实现 borsh
+// 实现了 PartialEq 和 Debug, 测试中可以直接 assert_eq!(escrow.terms(), expected)
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EscrowTerms {
+    pub seed: u64,
+    pub maker: [u8; 32],
+    pub mint_a: [u8; 32],
+    pub mint_b: [u8; 32],
+    pub receive: u64,
+    pub amount: u64,
+    pub deadline: i64,
+    pub bump: u8,
+    pub vault_bump: u8,
+    pub token_program: [u8; 32],
+    pub has_meta: bool,
+    pub grace_seconds: u32,
+}
+
+#[cfg(feature = "std")]
+impl EscrowTerms {
+    // 从链上 escrow 账户的原始数据解析
+    pub fn from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() != Escrow::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // 字段偏移量和 Escrow 一致:
+        // seed [0..8], maker [8..40], mint_a [40..72], mint_b [72..104], receive [104..112], amount [112..120],
+        // deadline [120..128], bump [128], vault_bump [129], token_program [130..162],
+        // has_meta [162], grace_seconds [163..167], _reserved [167]
+        Ok(Self {
+            seed: u64::from_le_bytes(data[0..8].try_into().unwrap()),
+            maker: data[8..40].try_into().unwrap(),
+            mint_a: data[40..72].try_into().unwrap(),
+            mint_b: data[72..104].try_into().unwrap(),
+            receive: u64::from_le_bytes(data[104..112].try_into().unwrap()),
+            amount: u64::from_le_bytes(data[112..120].try_into().unwrap()),
+            deadline: i64::from_le_bytes(data[120..128].try_into().unwrap()),
+            bump: data[128],
+            vault_bump: data[129],
+            token_program: data[130..162].try_into().unwrap(),
+            has_meta: data[162] != 0,
+            grace_seconds: u32::from_le_bytes(data[163..167].try_into().unwrap()),
+        })
+    }
+}
+
+// 链下计算 escrow PDA 地址, 和链上 Make 使用相同的 seeds
+// 返回 (escrow 地址, bump)
+#[cfg(feature = "std")]
+pub fn derive_escrow_address(maker: &Address, seed: u64) -> (Address, u8) {
+    Address::find_program_address(
+        &[Escrow::SEED, maker.as_ref(), &seed.to_le_bytes()],
+        &crate::ID,
+    )
+}
+
+// 全局统计数据 PDA, seeds = [b"stats"]
+// 可选账户, 指令中传入时才会更新, 不传的话指令照常执行
+#[repr(C)]
+pub struct Stats {
+    // Make 的总次数
+    pub total_makes: u64,
+    // Take 的总次数
+    pub total_takes: u64,
+    // Refund 的总次数
+    pub total_refunds: u64,
+    // Take 中 token b 的累计成交量
+    pub total_volume_b: u64,
+    // 缓存的 bump
+    pub bump: [u8; 1],
+    // 显式的填充字节, 和 Escrow::_reserved 一样
+    pub _reserved: [u8; 7],
+}
+
+const _: () = assert!(size_of::<Stats>() == Stats::LEN);
+
+impl Stats {
+    pub const SEED: &'static [u8] = b"stats";
+
+    // 计算 Stats 结构体的大小 bytes
+    pub const LEN: usize = size_of::<u64>() // 8 bytes (total_makes)
+        + size_of::<u64>() // 8 bytes (total_takes)
+        + size_of::<u64>() // 8 bytes (total_refunds)
+        + size_of::<u64>() // 8 bytes (total_volume_b)
+        + size_of::<[u8; 1]>() // 1 bytes (bump)
+        + size_of::<[u8; 7]>(); // 7 bytes (_reserved)
+
+    // 和 Escrow::load_mut 一样
+    #[inline(always)]
+    pub fn load_mut(bytes: &mut [u8]) -> Result<&mut Self, ProgramError> {
+        check_layout::<Self>(bytes)?;
+        Ok(unsafe { &mut *(bytes.as_mut_ptr() as *mut Self) })
+    }
+
+    // 和 Escrow::load 一样
+    #[inline(always)]
+    pub fn load(bytes: &[u8]) -> Result<&Self, ProgramError> {
+        check_layout::<Self>(bytes)?;
+        Ok(unsafe { &*(bytes.as_ptr() as *const Self) })
+    }
+
+    // 设置 bump 字段
+    #[inline(always)]
+    pub fn set_bump(&mut self, bump: [u8; 1]) {
         self.bump = bump;
     }
+
+    // 计数器使用 saturating_add, 溢出时停在 u64::MAX 而不是让指令失败
+    #[inline(always)]
+    pub fn record_make(&mut self) {
+        self.total_makes = self.total_makes.saturating_add(1);
+    }
+
+    #[inline(always)]
+    pub fn record_take(&mut self, volume_b: u64) {
+        self.total_takes = self.total_takes.saturating_add(1);
+        self.total_volume_b = self.total_volume_b.saturating_add(volume_b);
+    }
+
+    #[inline(always)]
+    pub fn record_refund(&mut self) {
+        self.total_refunds = self.total_refunds.saturating_add(1);
+    }
+}
+
+// escrow 的元数据 PDA, seeds = [b"escrow_meta", escrow]
+// 可选账户, 保存一个变长的 UTF-8 URI (比如挂单的描述页面)
+// Escrow 本身保持固定大小, 变长的数据放在单独的账户中
+// 布局: bump [0], uri [1..]
+pub struct EscrowMeta;
+
+impl EscrowMeta {
+    pub const SEED: &'static [u8] = b"escrow_meta";
+
+    // bump 占用的字节数
+    pub const HEADER_LEN: usize = size_of::<u8>();
+
+    // uri 的最大长度 bytes
+    // 账户大小 (HEADER_LEN + uri 长度) 不能等于 Escrow::LEN 或 Stats::LEN, 见 SetEscrowMeta
+    pub const MAX_URI_LEN: usize = 200;
+
+    // 读取 bump
+    #[inline(always)]
+    pub fn bump(bytes: &[u8]) -> Result<u8, ProgramError> {
+        bytes
+            .first()
+            .copied()
+            .ok_or(ProgramError::InvalidAccountData)
+    }
+
+    // 读取 uri, 写入时已经校验过是合法的 UTF-8
+    #[inline(always)]
+    pub fn uri(bytes: &[u8]) -> Result<&str, ProgramError> {
+        if bytes.len() < Self::HEADER_LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        core::str::from_utf8(&bytes[Self::HEADER_LEN..])
+            .map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 按 8 字节对齐的账户数据, 和运行时传入的账户数据一样
+    #[repr(C, align(8))]
+    struct Aligned([u8; Escrow::LEN]);
+
+    fn address(byte: u8) -> Address {
+        Address::new_from_array([byte; 32])
+    }
+
+    // 每个字段都写入不同的值, 字段的偏移量错位时测试会失败
+    fn escrow_data() -> Aligned {
+        let mut data = Aligned([0; Escrow::LEN]);
+        Escrow::load_mut(&mut data.0).unwrap().set_inner(
+            42,
+            address(1),
+            address(2),
+            address(3),
+            1_000,
+            500,
+            1_700_000_000,
+            [254],
+            [253],
+            address(4),
+        );
+        data
+    }
+
+    #[test]
+    fn getters_read_back_set_inner() {
+        let data = escrow_data();
+        let escrow = Escrow::load(&data.0).unwrap();
+
+        assert_eq!(escrow.seed(), 42);
+        assert_eq!(escrow.maker(), &address(1));
+        assert_eq!(escrow.mint_a(), &address(2));
+        assert_eq!(escrow.mint_b(), &address(3));
+        assert_eq!(escrow.receive(), 1_000);
+        assert_eq!(escrow.amount(), 500);
+        assert_eq!(escrow.deadline(), 1_700_000_000);
+        assert_eq!(escrow.bump(), 254);
+        assert_eq!(escrow.vault_bump(), 253);
+        assert_eq!(escrow.token_program(), &address(4));
+        assert!(!escrow.has_meta());
+        assert_eq!(escrow.grace_seconds(), 0);
+    }
+
+    #[test]
+    fn setters_update_single_fields() {
+        let mut data = escrow_data();
+        let escrow = Escrow::load_mut(&mut data.0).unwrap();
+
+        escrow.set_receive(7);
+        escrow.set_amount(8);
+        escrow.set_deadline(0);
+        escrow.set_has_meta(true);
+        escrow.set_grace_seconds(600);
+
+        assert_eq!(escrow.receive(), 7);
+        assert_eq!(escrow.amount(), 8);
+        assert_eq!(escrow.deadline(), 0);
+        assert!(escrow.has_meta());
+        assert_eq!(escrow.grace_seconds(), 600);
+        // 其他字段保持不变
+        assert_eq!(escrow.seed(), 42);
+        assert_eq!(escrow.token_program(), &address(4));
+
+        // set_inner 会清除 has_meta 和 grace_seconds
+        escrow.set_inner(
+            1,
+            address(1),
+            address(2),
+            address(3),
+            1,
+            1,
+            0,
+            [1],
+            [1],
+            address(4),
+        );
+        assert!(!escrow.has_meta());
+        assert_eq!(escrow.grace_seconds(), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn terms_from_account_data() {
+        let mut data = escrow_data();
+        Escrow::load_mut(&mut data.0)
+            .unwrap()
+            .set_grace_seconds(0x0102_0304);
+        let terms = EscrowTerms::from_account_data(&data.0).unwrap();
+
+        assert_eq!(terms, Escrow::load(&data.0).unwrap().terms());
+        assert_eq!(
+            terms,
+            EscrowTerms {
+                seed: 42,
+                maker: [1; 32],
+                mint_a: [2; 32],
+                mint_b: [3; 32],
+                receive: 1_000,
+                amount: 500,
+                deadline: 1_700_000_000,
+                bump: 254,
+                vault_bump: 253,
+                token_program: [4; 32],
+                has_meta: false,
+                grace_seconds: 0x0102_0304,
+            }
+        );
+
+        assert_eq!(
+            EscrowTerms::from_account_data(&data.0[..Escrow::LEN - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn terms_borsh_round_trip() {
+        let terms = EscrowTerms::from_account_data(&escrow_data().0).unwrap();
+        let bytes = borsh::to_vec(&terms).unwrap();
+        assert_eq!(EscrowTerms::try_from_slice(&bytes).unwrap(), terms);
+    }
+
+    // 链上用保存的 bump 计算的地址, 必须和 find_program_address 找到的 canonical 地址一致
+    #[test]
+    fn derive_address_matches_find_program_address() {
+        let maker = address(9);
+        for seed in [0, 1, 42, u64::MAX - 256] {
+            let (expected, bump) = Address::find_program_address(
+                &[Escrow::SEED, maker.as_ref(), &seed.to_le_bytes()],
+                &crate::ID,
+            );
+            assert_eq!(
+                Escrow::derive_address(&maker, seed, bump).unwrap(),
+                expected
+            );
+
+            // 其他 bump 得到的不是同一个地址 (或者落在曲线上, 无法作为 PDA)
+            match Escrow::derive_address(&maker, seed, bump.wrapping_sub(1)) {
+                Ok(other) => assert_ne!(other, expected),
+                Err(err) => assert_eq!(err, EscrowError::InvalidEscrowPda.into()),
+            }
+        }
+    }
+
+    // 客户端计算的地址和 bump, 链上用同样的 bump 校验时必须通过
+    #[cfg(feature = "std")]
+    #[test]
+    fn client_derivation_matches_on_chain() {
+        let maker = address(9);
+        for seed in [0, 1, 42, u64::MAX - 256] {
+            let (escrow, bump) = derive_escrow_address(&maker, seed);
+            assert_eq!(Escrow::derive_address(&maker, seed, bump).unwrap(), escrow);
+        }
+    }
+
+    // 长度正确但起始地址没有对齐的数据返回错误, 而不是未定义行为
+    #[test]
+    fn load_rejects_misaligned_data() {
+        #[repr(C, align(8))]
+        struct Buffer([u8; Escrow::LEN + 1]);
+        let mut buffer = Buffer([0; Escrow::LEN + 1]);
+
+        assert!(Escrow::load(&buffer.0[..Escrow::LEN]).is_ok());
+        assert_eq!(
+            Escrow::load(&buffer.0[1..]).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            Escrow::load_mut(&mut buffer.0[1..]).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            Stats::load(&buffer.0[1..=Stats::LEN]).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
+
+        // 长度不对
+        assert_eq!(
+            Escrow::load(&buffer.0[..Escrow::LEN - 1]).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
+    }
 }
@@ -0,0 +1,102 @@
+// 单元测试中构造 AccountView 的工具
+// 按照运行时的内存布局, 在 RuntimeAccount 头部后面紧跟账户数据
+use pinocchio::{AccountView, Address};
+use solana_account_view::{RuntimeAccount, NOT_BORROWED};
+
+pub struct TestAccount {
+    // 用 u64 保证 8 字节对齐, 和运行时传入的账户一致
+    buffer: Vec<u64>,
+}
+
+impl TestAccount {
+    pub fn new(address: Address, owner: Address, data: &[u8]) -> Self {
+        let header = size_of::<RuntimeAccount>();
+        let mut buffer = vec![0u64; (header + data.len()).div_ceil(size_of::<u64>())];
+        let raw = buffer.as_mut_ptr() as *mut RuntimeAccount;
+
+        // buffer 的大小足够放下头部和数据, 并且按 8 字节对齐
+        unsafe {
+            raw.write(RuntimeAccount {
+                borrow_state: NOT_BORROWED,
+                address,
+                owner,
+                data_len: data.len() as u64,
+                ..Default::default()
+            });
+            core::ptr::copy_nonoverlapping(data.as_ptr(), (raw as *mut u8).add(header), data.len());
+        }
+
+        Self { buffer }
+    }
+
+    // 没有数据, 由 system program 拥有的账户
+    pub fn empty(address: Address) -> Self {
+        Self::new(address, Address::default(), &[])
+    }
+
+    pub fn signer(mut self) -> Self {
+        unsafe { (*self.raw()).is_signer = 1 };
+        self
+    }
+
+    pub fn writable(mut self) -> Self {
+        unsafe { (*self.raw()).is_writable = 1 };
+        self
+    }
+
+    pub fn lamports(mut self, lamports: u64) -> Self {
+        unsafe { (*self.raw()).lamports = lamports };
+        self
+    }
+
+    fn raw(&mut self) -> *mut RuntimeAccount {
+        self.buffer.as_mut_ptr() as *mut RuntimeAccount
+    }
+
+    // 返回的 AccountView 指向 buffer, 不能比 TestAccount 活得更久
+    pub fn view(&mut self) -> AccountView {
+        unsafe { AccountView::new_unchecked(self.raw()) }
+    }
+}
+
+// 构造 count 个互不相同的空账户
+pub fn empty_accounts(count: usize) -> Vec<TestAccount> {
+    (0..count)
+        .map(|i| TestAccount::empty(Address::new_from_array([i as u8 + 1; 32])))
+        .collect()
+}
+
+pub fn views(accounts: &mut [TestAccount]) -> Vec<AccountView> {
+    accounts.iter_mut().map(TestAccount::view).collect()
+}
+
+// maker 的 escrow PDA 地址和 canonical bump
+pub fn escrow_address(maker: &Address, seed: u64) -> (Address, u8) {
+    Address::find_program_address(
+        &[
+            crate::state::Escrow::SEED,
+            maker.as_ref(),
+            &seed.to_le_bytes(),
+        ],
+        &crate::ID,
+    )
+}
+
+// spl token 的 ATA 地址和 canonical bump
+pub fn ata_address(wallet: &Address, mint: &Address) -> (Address, u8) {
+    Address::find_program_address(
+        &[wallet.as_ref(), pinocchio_token::ID.as_ref(), mint.as_ref()],
+        &pinocchio_associated_token_account::ID,
+    )
+}
+
+// spl token 的 token account 数据 (TokenAccount::LEN 字节), state 为 Initialized
+// mint [0..32], owner [32..64], amount [64..72], state [108]
+pub fn token_account_data(mint: &Address, owner: &Address, amount: u64) -> Vec<u8> {
+    let mut data = vec![0u8; pinocchio_token::state::TokenAccount::LEN];
+    data[0..32].copy_from_slice(mint.as_ref());
+    data[32..64].copy_from_slice(owner.as_ref());
+    data[64..72].copy_from_slice(&amount.to_le_bytes());
+    data[108] = 1;
+    data
+}